@@ -0,0 +1,218 @@
+//! Sequences from iterated chaotic maps. Generators are written as the name
+//! of the map followed by `name=value` parameters, e.g.
+//!
+//! ```text
+//! logistic r=3.9 x0=0.2 length=200 range=110:880
+//! henon a=1.4 b=0.3
+//! tent mu=1.99 skip=500
+//! ```
+//!
+//! The maps and their parameters are
+//!
+//! - `logistic`: `x' = r x (1 - x)` with `r` in (0, 4], default 3.99.
+//! - `henon`: `x' = 1 - a x² + y`, `y' = b x` with `a` and `b`, default 1.4
+//!   and 0.3, and the start `y0`, default 0.
+//! - `tent`: `x' = mu min(x, 1 - x)` with `mu` in (0, 2], default 1.99.
+//!
+//! All maps start at `x0`, default 0.1, drop the first `skip` iterations,
+//! default 0, and generate `length` values, default 100. The values of `x`
+//! are scaled from [0, 1], or [-1.5, 1.5] for the Hénon map, to the `range`
+//! `min:max` of the target. Waveforms are chosen from a list instead, each
+//! value being the position of a waveform in the list.
+
+use crate::error::{Error, Result};
+use crate::pitch::{parse_frequency, Tuning};
+use crate::sequence::{parse_float, tokens, Parameter, Token, INLINE_NAME};
+use crate::wave::Wave;
+
+pub const DEFAULT_LENGTH: usize = 100;
+
+/// The range of frequencies in Hz unless the generator gives one.
+pub const DEFAULT_FREQUENCY_RANGE: (f64, f64) = (110.0, 880.0);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChaoticMap {
+    Logistic { r: f64 },
+    Henon { a: f64, b: f64 },
+    Tent { mu: f64 },
+}
+
+impl ChaoticMap {
+    fn parse(name: &str) -> Option<ChaoticMap> {
+        match name {
+            "logistic" => Some(ChaoticMap::Logistic { r: 3.99 }),
+            "henon" => Some(ChaoticMap::Henon { a: 1.4, b: 0.3 }),
+            "tent" => Some(ChaoticMap::Tent { mu: 1.99 }),
+            _ => None,
+        }
+    }
+
+    fn step(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            ChaoticMap::Logistic { r } => (r * x * (1.0 - x), 0.0),
+            ChaoticMap::Henon { a, b } => (1.0 - a * x * x + y, b * x),
+            ChaoticMap::Tent { mu } => (mu * x.min(1.0 - x), 0.0),
+        }
+    }
+
+    /// Scales `x` to [0, 1].
+    fn normalize(self, x: f64) -> f64 {
+        match self {
+            ChaoticMap::Henon { .. } => ((x + 1.5) / 3.0).clamp(0.0, 1.0),
+            _ => x,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chaos {
+    pub map: ChaoticMap,
+    pub x0: f64,
+    pub y0: f64,
+    /// Number of iterations dropped before the first value.
+    pub skip: usize,
+    pub length: usize,
+}
+
+impl Chaos {
+    /// The values of the orbit scaled to [0, 1], or `None` if it diverges.
+    pub fn values(&self) -> Option<Vec<f64>> {
+        let mut point = (self.x0, self.y0);
+        let step = |(x, y): (f64, f64)| {
+            let (x, y) = self.map.step(x, y);
+            Some((x, y)).filter(|_| x.is_finite() && y.is_finite())
+        };
+        for _ in 0..self.skip {
+            point = step(point)?;
+        }
+        let mut values = vec![];
+        for _ in 0..self.length {
+            values.push(self.map.normalize(point.0));
+            point = step(point)?;
+        }
+        Some(values)
+    }
+}
+
+/// Reads a bound of `range=min:max`.
+type ParseBound<'p> = &'p dyn Fn(&str) -> std::result::Result<f64, String>;
+
+/// Parses a generator, `range` is only accepted if `parse_bound` reads its
+/// bounds.
+fn parse_generator(
+    tokens: &[Token],
+    parse_bound: Option<ParseBound>,
+) -> Result<(Chaos, Option<(f64, f64)>)> {
+    let (name, parameters) = tokens
+        .split_first()
+        .ok_or(Error::EmptySequence("chaotic map"))?;
+    let map = ChaoticMap::parse(name.text)
+        .ok_or_else(|| name.error("expected a map: logistic, henon or tent"))?;
+    let mut chaos = Chaos {
+        map,
+        x0: 0.1,
+        y0: 0.0,
+        skip: 0,
+        length: DEFAULT_LENGTH,
+    };
+    let mut range = None;
+    for &token in parameters {
+        let parameter = Parameter::parse(token)?;
+        let number = || parameter.number();
+        let unit = || {
+            number()
+                .ok()
+                .filter(|v| (0.0..=1.0).contains(v))
+                .ok_or_else(|| parameter.error("expected a number in [0, 1]"))
+        };
+        match (parameter.name, &mut chaos.map) {
+            ("x0", ChaoticMap::Henon { .. }) => chaos.x0 = number()?,
+            ("x0", _) => chaos.x0 = unit()?,
+            ("y0", ChaoticMap::Henon { .. }) => chaos.y0 = number()?,
+            ("r", ChaoticMap::Logistic { r }) => {
+                *r = number()?;
+                if !(*r > 0.0 && *r <= 4.0) {
+                    return Err(parameter.error("expected r in (0, 4]"));
+                }
+            }
+            ("a", ChaoticMap::Henon { a, .. }) => *a = number()?,
+            ("b", ChaoticMap::Henon { b, .. }) => *b = number()?,
+            ("mu", ChaoticMap::Tent { mu }) => {
+                *mu = number()?;
+                if !(*mu > 0.0 && *mu <= 2.0) {
+                    return Err(parameter.error("expected mu in (0, 2]"));
+                }
+            }
+            ("skip", _) => chaos.skip = parameter.count(0)?,
+            ("length", _) => chaos.length = parameter.count(1)?,
+            ("range", _) => {
+                let parse = parse_bound.ok_or_else(|| parameter.unknown())?;
+                let bounds = parameter
+                    .value
+                    .split_once(':')
+                    .ok_or_else(|| parameter.error("expected min:max"))?;
+                let min = parse(bounds.0).map_err(|message| parameter.error(&message))?;
+                let max = parse(bounds.1).map_err(|message| parameter.error(&message))?;
+                range = Some((min, max));
+            }
+            _ => return Err(parameter.unknown()),
+        }
+    }
+    Ok((chaos, range))
+}
+
+fn generate(tokens: &[Token], chaos: &Chaos) -> Result<Vec<f64>> {
+    chaos
+        .values()
+        .ok_or_else(|| tokens[0].error("the map diverges with these parameters"))
+}
+
+/// Values of a generator scaled to its `range`, by default `default_range`.
+pub(crate) fn chaos_float_tokens(tokens: &[Token], default_range: (f64, f64)) -> Result<Vec<f64>> {
+    let (chaos, range) = parse_generator(tokens, Some(&parse_float))?;
+    let (min, max) = range.unwrap_or(default_range);
+    let values = generate(tokens, &chaos)?;
+    Ok(values.iter().map(|v| min + v * (max - min)).collect())
+}
+
+/// Frequencies of a generator, the bounds of its `range` are read like any
+/// frequency.
+pub(crate) fn chaos_frequency_tokens(tokens: &[Token], tuning: &Tuning) -> Result<Vec<f64>> {
+    let parse = |t: &str| parse_frequency(t, tuning);
+    let (chaos, range) = parse_generator(tokens, Some(&parse))?;
+    let (min, max) = range.unwrap_or(DEFAULT_FREQUENCY_RANGE);
+    let values = generate(tokens, &chaos)?;
+    Ok(values.iter().map(|v| min + v * (max - min)).collect())
+}
+
+/// Waveforms chosen from `waves`, the value 1 picks the last one.
+pub(crate) fn chaos_wave_tokens(tokens: &[Token], waves: &[Wave]) -> Result<Vec<Wave>> {
+    if waves.is_empty() {
+        return Err(Error::EmptySequence("waveforms"));
+    }
+    let (chaos, _) = parse_generator(tokens, None)?;
+    let values = generate(tokens, &chaos)?;
+    let last = waves.len() - 1;
+    Ok(values
+        .iter()
+        .map(|v| waves[((v * waves.len() as f64) as usize).min(last)])
+        .collect())
+}
+
+/// Values of a generator such as `logistic r=3.9 range=0:0.5`.
+pub fn chaos_floats(generator: &str, default_range: (f64, f64)) -> Result<Vec<f64>> {
+    let tokens: Vec<Token> = tokens(generator).collect();
+    chaos_float_tokens(&tokens, default_range).map_err(|e| e.in_file(INLINE_NAME))
+}
+
+/// Frequencies of a generator such as `henon length=200 range=A2:A5`.
+pub fn chaos_frequencies(generator: &str, tuning: &Tuning) -> Result<Vec<f64>> {
+    let tokens: Vec<Token> = tokens(generator).collect();
+    chaos_frequency_tokens(&tokens, tuning).map_err(|e| e.in_file(INLINE_NAME))
+}
+
+/// Waveforms of a generator such as `tent mu=1.9`, chosen from `waves`.
+pub fn chaos_waves(generator: &str, waves: &[Wave]) -> Result<Vec<Wave>> {
+    let tokens: Vec<Token> = tokens(generator).collect();
+    chaos_wave_tokens(&tokens, waves).map_err(|e| e.in_file(INLINE_NAME))
+}
@@ -0,0 +1,69 @@
+/// Length of a segment, used instead of a frequency in duration mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Duration {
+    Samples(f64),
+    Seconds(f64),
+}
+
+/// Unit of durations written without a suffix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DurationUnit {
+    Samples,
+    Milliseconds,
+    Seconds,
+}
+
+impl Duration {
+    pub fn new(value: f64, unit: DurationUnit) -> Duration {
+        match unit {
+            DurationUnit::Samples => Duration::Samples(value),
+            DurationUnit::Milliseconds => Duration::Seconds(value / 1000.0),
+            DurationUnit::Seconds => Duration::Seconds(value),
+        }
+    }
+
+    pub fn value(self) -> f64 {
+        match self {
+            Duration::Samples(v) | Duration::Seconds(v) => v,
+        }
+    }
+
+    /// The duration in samples at `sample_rate`. Sample durations refer to
+    /// the output rate and are scaled by the oversampling factor.
+    pub fn in_samples(self, sample_rate: u32, oversample: u32) -> f64 {
+        match self {
+            Duration::Samples(n) => n * oversample as f64,
+            Duration::Seconds(s) => s * sample_rate as f64,
+        }
+    }
+}
+
+impl DurationUnit {
+    pub fn parse(unit: &str) -> Option<DurationUnit> {
+        match unit {
+            "smp" | "samples" => Some(DurationUnit::Samples),
+            "ms" => Some(DurationUnit::Milliseconds),
+            "s" => Some(DurationUnit::Seconds),
+            _ => None,
+        }
+    }
+}
+
+/// Parses durations such as `480smp`, `12.5ms` or `0.1s`. Plain numbers are
+/// read in `default_unit`.
+pub fn parse_duration(token: &str, default_unit: DurationUnit) -> Result<Duration, String> {
+    let split = token
+        .find(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E')
+        .unwrap_or(token.len());
+    let (number, unit) = token.split_at(split);
+    let unit = if unit.is_empty() {
+        default_unit
+    } else {
+        DurationUnit::parse(unit)
+            .ok_or_else(|| String::from("expected a duration unit (smp, ms, s)"))?
+    };
+    number
+        .parse::<f64>()
+        .map(|v| Duration::new(v, unit))
+        .map_err(|_| String::from("expected a duration"))
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::io;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Position of an offending token. Lines and columns start at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    Wav {
+        path: String,
+        source: hound::Error,
+    },
+    Parse {
+        location: Location,
+        token: String,
+        message: String,
+    },
+    MissingKey {
+        path: Option<String>,
+        key: &'static str,
+    },
+    EmptySequence(&'static str),
+    InvalidValue {
+        sequence: &'static str,
+        index: usize,
+        value: f64,
+        expected: &'static str,
+    },
+    InvalidSampleRate,
+    InvalidOversample(u32),
+    InvalidLengthPolicy(String),
+    InvalidBaseFrequency(String),
+    InvalidMaxDuration(f64),
+    InvalidGenerator {
+        generator: &'static str,
+        message: String,
+    },
+    SampleLimit {
+        limit: usize,
+        segment: usize,
+    },
+    InChannel(usize, Box<Error>),
+    ChannelsDeclared(usize),
+    NoChannels,
+    StdinReused,
+    EmptyWavetable(String),
+    UnsupportedFormat {
+        float: bool,
+        bits: u16,
+    },
+    UnsupportedLayout {
+        channels: u16,
+        sample_rate: u32,
+        bits: u16,
+    },
+}
+
+impl Error {
+    pub(crate) fn io(path: &str, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    /// Tags errors with the 1-based channel number in multichannel scores.
+    pub(crate) fn in_channel(self, channel: usize, multichannel: bool) -> Error {
+        if multichannel {
+            Error::InChannel(channel + 1, Box::new(self))
+        } else {
+            self
+        }
+    }
+
+    /// Attaches a file path to errors that were produced while parsing text.
+    /// Errors of files that were read in turn keep their own path.
+    pub(crate) fn in_file(self, file_path: &str) -> Error {
+        match self {
+            Error::Parse {
+                mut location,
+                token,
+                message,
+            } => {
+                location.path.get_or_insert_with(|| file_path.to_string());
+                Error::Parse {
+                    location,
+                    token,
+                    message,
+                }
+            }
+            Error::MissingKey { path: None, key } => Error::MissingKey {
+                path: Some(file_path.to_string()),
+                key,
+            },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}:{}", path, self.line, self.column),
+            None => write!(f, "{}:{}", self.line, self.column),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "{}: {}", path, source),
+            Error::Wav { path, source } => write!(f, "{}: {}", path, source),
+            Error::Parse {
+                location,
+                token,
+                message,
+            } => write!(f, "{}: {} (found `{}`)", location, message, token),
+            Error::MissingKey {
+                path: Some(path),
+                key,
+            } => {
+                write!(f, "{}: missing key `{}`", path, key)
+            }
+            Error::MissingKey { path: None, key } => write!(f, "missing key `{}`", key),
+            Error::EmptySequence(name) => write!(f, "the {} sequence is empty", name),
+            Error::InvalidValue {
+                sequence,
+                index,
+                value,
+                expected,
+            } => write!(
+                f,
+                "invalid {} {} at index {}, expected {}",
+                sequence, value, index, expected
+            ),
+            Error::InvalidSampleRate => write!(f, "the sample rate must be positive"),
+            Error::InvalidOversample(factor) => {
+                write!(f, "invalid oversampling factor {}", factor)
+            }
+            Error::SampleLimit { limit, segment } => write!(
+                f,
+                "rendering stopped at segment {} after reaching the limit of {} samples",
+                segment, limit
+            ),
+            Error::InvalidLengthPolicy(policy) => write!(
+                f,
+                "invalid length policy `{}`, expected longest, shortest, lcm, \
+                 a positive segment count or a repeat factor such as 3x",
+                policy
+            ),
+            Error::InvalidBaseFrequency(base) => {
+                write!(f, "invalid base frequency `{}`", base)
+            }
+            Error::InvalidMaxDuration(limit) => {
+                write!(
+                    f,
+                    "invalid render limit {}, expected a non-negative number",
+                    limit
+                )
+            }
+            Error::InvalidGenerator { generator, message } => {
+                write!(f, "invalid {} generator: {}", generator, message)
+            }
+            Error::InChannel(channel, e) => write!(f, "channel {}: {}", channel, e),
+            Error::ChannelsDeclared(channels) => write!(
+                f,
+                "the score declares {} channels, which --channels would replace",
+                channels
+            ),
+            Error::NoChannels => write!(f, "--channels expects at least one channel"),
+            Error::StdinReused => write!(f, "standard input can only be read once"),
+            Error::EmptyWavetable(path) => write!(f, "{}: the wavetable is empty", path),
+            Error::UnsupportedFormat { float, bits } => write!(
+                f,
+                "unsupported sample format: {}-bit {}",
+                bits,
+                if *float { "float" } else { "integer" }
+            ),
+            Error::UnsupportedLayout {
+                channels,
+                sample_rate,
+                bits,
+            } => write!(
+                f,
+                "{} channels of {}-bit samples at {} Hz exceed the limits of a WAV header",
+                channels, bits, sample_rate
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Wav { source, .. } => Some(source),
+            Error::InChannel(_, e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
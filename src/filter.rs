@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+/// Number of filter taps per unit of the decimation factor.
+const TAPS_PER_FACTOR: usize = 64;
+
+/// Windowed-sinc low-pass filter for decimation by `factor`. The cutoff lies
+/// at the new Nyquist frequency, so aliasing is confined to the top of the
+/// output band.
+pub fn lowpass_kernel(factor: usize) -> Vec<f64> {
+    let taps = TAPS_PER_FACTOR * factor + 1;
+    let center = (taps / 2) as f64;
+    let cutoff = 0.5 / factor as f64;
+    let kernel: Vec<f64> = (0..taps)
+        .map(|i| {
+            let x = i as f64 - center;
+            let sinc = if x == 0.0 {
+                2.0 * cutoff
+            } else {
+                (2.0 * PI * cutoff * x).sin() / (PI * x)
+            };
+            let window = 0.42 - 0.5 * (2.0 * PI * i as f64 / (taps - 1) as f64).cos()
+                + 0.08 * (4.0 * PI * i as f64 / (taps - 1) as f64).cos();
+            sinc * window
+        })
+        .collect();
+    let gain: f64 = kernel.iter().sum();
+    kernel.iter().map(|k| k / gain).collect()
+}
+
+/// Low-pass filters its input and keeps every `factor`th sample. Input
+/// samples are pushed one at a time and output samples become available as
+/// soon as the filter has seen all of their input. The filter delay is
+/// compensated, so output sample `m` corresponds to input sample
+/// `m * factor`.
+#[derive(Debug, Clone)]
+pub struct Decimator {
+    kernel: Vec<f64>,
+    factor: usize,
+    buffer: VecDeque<f64>,
+    /// Input index of the first buffered sample.
+    start: usize,
+    /// Index of the next output sample.
+    next: usize,
+    finished: bool,
+}
+
+impl Decimator {
+    pub fn new(factor: usize) -> Decimator {
+        let factor = factor.max(1);
+        Decimator {
+            kernel: if factor == 1 {
+                vec![1.0]
+            } else {
+                lowpass_kernel(factor)
+            },
+            factor,
+            buffer: VecDeque::new(),
+            start: 0,
+            next: 0,
+            finished: false,
+        }
+    }
+
+    pub fn push(&mut self, sample: f64) {
+        self.buffer.push_back(sample);
+    }
+
+    /// Marks the end of the input, the remaining output is filtered as if
+    /// the input continued with silence.
+    pub fn finish(&mut self) {
+        self.finished = true;
+    }
+
+    pub fn pop(&mut self) -> Option<f64> {
+        let center = self.kernel.len() / 2;
+        let received = self.start + self.buffer.len();
+        let n = self.next * self.factor;
+        if (self.finished && n >= received) || (!self.finished && received <= n + center) {
+            return None;
+        }
+
+        let output = self
+            .kernel
+            .iter()
+            .enumerate()
+            .filter_map(|(k, h)| {
+                (n + center)
+                    .checked_sub(k)
+                    .filter(|&i| i >= self.start)
+                    .and_then(|i| self.buffer.get(i - self.start))
+                    .map(|x| h * x)
+            })
+            .sum();
+
+        self.next += 1;
+        let keep_from = (self.next * self.factor).saturating_sub(center);
+        while self.start < keep_from && self.buffer.pop_front().is_some() {
+            self.start += 1;
+        }
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimate(input: &[f64], factor: usize) -> Vec<f64> {
+        let mut decimator = Decimator::new(factor);
+        let mut output = vec![];
+        for &x in input {
+            decimator.push(x);
+            output.extend(decimator.pop());
+        }
+        decimator.finish();
+        while let Some(y) = decimator.pop() {
+            output.push(y);
+        }
+        output
+    }
+
+    #[test]
+    fn output_lengths_round_up() {
+        for factor in 1..=4 {
+            for n in 0..40usize {
+                let expected = n.div_ceil(factor);
+                assert_eq!(decimate(&vec![0.5; n], factor).len(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn factor_one_passes_samples_through() {
+        let input = [0.1, -0.4, 0.9, 0.0, 0.3];
+        assert_eq!(decimate(&input, 1), input);
+    }
+
+    #[test]
+    fn filter_delay_is_compensated() {
+        // A slow sine at 48 kHz stays put by the low-pass filter, so output
+        // sample `m` matches input sample `m * factor` away from the ends.
+        let factor = 4;
+        let sine = |i: usize| (2.0 * PI * 100.0 * i as f64 / 48_000.0).sin();
+        let input: Vec<f64> = (0..48_000).map(sine).collect();
+        let output = decimate(&input, factor);
+        let margin = TAPS_PER_FACTOR;
+        for (m, y) in output
+            .iter()
+            .enumerate()
+            .skip(margin)
+            .take(output.len() - 2 * margin)
+        {
+            assert!((y - sine(m * factor)).abs() < 1e-3, "sample {} is {}", m, y);
+        }
+        // An impulse comes out as the peak of the kernel.
+        let mut impulse = vec![0.0; 4000];
+        impulse[2000] = 1.0;
+        let output = decimate(&impulse, factor);
+        let peak = (0..output.len())
+            .max_by(|&a, &b| output[a].partial_cmp(&output[b]).unwrap())
+            .unwrap();
+        assert_eq!(peak, 2000 / factor);
+    }
+}
@@ -0,0 +1,302 @@
+//! Dynamic stochastic synthesis after Xenakis' GENDYN. A cycle of `points`
+//! segments is repeated, and from one cycle to the next the duration and
+//! amplitude of every segment take a step of a random walk. The steps
+//! themselves walk randomly, both walks are folded back into their bounds by
+//! mirror barriers.
+//!
+//! The amplitudes scale the waveforms of the score like any other amplitudes.
+//! Xenakis' waveform, straight lines from the amplitude of one breakpoint to
+//! the next, is drawn with the ramp waveform `l`, e.g.
+//! `--waveforms l --gendyn points=12`.
+//!
+//! Generators are written as `name=value` pairs, e.g.
+//!
+//! ```text
+//! points=12 cycles=200 distribution=cauchy:0.2
+//! durations=0.05ms:1ms:0.02ms amplitudes=-1:1:0.05
+//! ```
+
+use crate::duration::{parse_duration, Duration, DurationUnit};
+use crate::error::{Error, Result};
+use crate::rng::Rng;
+use crate::sequence::{tokens, Parameter, Token, INLINE_NAME, MAX_GENERATED_LENGTH};
+
+/// Mixed into the seed of the score so that the random walks draw from other
+/// random numbers than the noise of the first channel.
+const GENDYN_STREAM: u64 = 0x676e_6479;
+
+/// Distribution of the steps of a random walk, each with a scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Distribution {
+    Uniform(f64),
+    Cauchy(f64),
+    Logistic(f64),
+}
+
+impl Distribution {
+    /// Parses `uniform`, `cauchy:0.1` or `logistic:0.5`, the scale defaults
+    /// to 1.
+    pub fn parse(distribution: &str) -> Option<Distribution> {
+        let (name, scale) = distribution.split_once(':').unwrap_or((distribution, "1"));
+        let scale = scale
+            .parse::<f64>()
+            .ok()
+            .filter(|s| s.is_finite() && *s > 0.0)?;
+        match name {
+            "uniform" => Some(Distribution::Uniform(scale)),
+            "cauchy" => Some(Distribution::Cauchy(scale)),
+            "logistic" => Some(Distribution::Logistic(scale)),
+            _ => None,
+        }
+    }
+
+    pub fn sample(self, rng: &mut Rng) -> f64 {
+        // Keep away from 0 and 1, where the inverse distributions diverge.
+        let u = rng.next_f64().clamp(1e-12, 1.0 - 1e-12);
+        match self {
+            Distribution::Uniform(a) => a * (2.0 * u - 1.0),
+            Distribution::Cauchy(a) => a * (std::f64::consts::PI * (u - 0.5)).tan(),
+            Distribution::Logistic(a) => a * (u / (1.0 - u)).ln(),
+        }
+    }
+}
+
+/// Folds `value` back into [low, high] as if reflected by mirrors at both
+/// bounds.
+pub fn mirror(value: f64, low: f64, high: f64) -> f64 {
+    let range = high - low;
+    if range.is_nan() || range <= 0.0 || !value.is_finite() {
+        return low;
+    }
+    let x = (value - low).rem_euclid(2.0 * range);
+    low + if x > range { 2.0 * range - x } else { x }
+}
+
+/// A second order random walk: the step walks within [-max_step, max_step],
+/// the value within [min, max].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RandomWalk<T> {
+    pub min: T,
+    pub max: T,
+    pub max_step: T,
+    pub distribution: Distribution,
+}
+
+impl RandomWalk<f64> {
+    fn walk(&self, value: &mut f64, step: &mut f64, rng: &mut Rng) {
+        let draw = self.distribution.sample(rng) * self.max_step;
+        *step = mirror(*step + draw, -self.max_step, self.max_step);
+        *value = mirror(*value + *step, self.min, self.max);
+    }
+}
+
+/// The state of one segment of the cycle.
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    duration: f64,
+    duration_step: f64,
+    amplitude: f64,
+    amplitude_step: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gendyn {
+    /// Number of segments per cycle.
+    pub points: usize,
+    pub cycles: usize,
+    pub durations: RandomWalk<Duration>,
+    pub amplitudes: RandomWalk<f64>,
+}
+
+impl Default for Gendyn {
+    fn default() -> Gendyn {
+        let distribution = Distribution::Cauchy(0.2);
+        Gendyn {
+            points: 12,
+            cycles: 100,
+            durations: RandomWalk {
+                min: Duration::Seconds(0.000_05),
+                max: Duration::Seconds(0.001),
+                max_step: Duration::Seconds(0.000_02),
+                distribution,
+            },
+            amplitudes: RandomWalk {
+                min: -1.0,
+                max: 1.0,
+                max_step: 0.05,
+                distribution,
+            },
+        }
+    }
+}
+
+impl Gendyn {
+    /// The number of generated segments, `None` if it exceeds
+    /// `MAX_GENERATED_LENGTH`.
+    pub fn segment_count(&self) -> Option<usize> {
+        self.points
+            .checked_mul(self.cycles)
+            .filter(|&n| n <= MAX_GENERATED_LENGTH)
+    }
+
+    /// Checks the segment count and that the duration bounds, which may be
+    /// given in different units, are ordered at `sample_rate`.
+    pub fn validate(&self, sample_rate: u32) -> Result<()> {
+        let invalid = |message: String| Error::InvalidGenerator {
+            generator: "GENDYN",
+            message,
+        };
+        if self.segment_count().is_none() {
+            return Err(invalid(format!(
+                "points * cycles exceeds {} segments",
+                MAX_GENERATED_LENGTH
+            )));
+        }
+        let samples = |d: Duration| d.in_samples(sample_rate, 1);
+        if samples(self.durations.min) > samples(self.durations.max) {
+            return Err(invalid(String::from(
+                "the minimum duration exceeds the maximum",
+            )));
+        }
+        Ok(())
+    }
+
+    /// The durations, in samples at `sample_rate`, and the amplitudes of
+    /// `points * cycles` segments. Every segment starts in the middle of its
+    /// bounds, `seed` is the seed of the score.
+    pub fn generate(&self, sample_rate: u32, seed: u64) -> (Vec<Duration>, Vec<f64>) {
+        let samples = |d: Duration| d.in_samples(sample_rate, 1);
+        let durations = RandomWalk {
+            min: samples(self.durations.min),
+            max: samples(self.durations.max),
+            max_step: samples(self.durations.max_step),
+            distribution: self.durations.distribution,
+        };
+        let mut rng = Rng::new(seed ^ GENDYN_STREAM);
+        let start = Point {
+            duration: (durations.min + durations.max) / 2.0,
+            duration_step: 0.0,
+            amplitude: (self.amplitudes.min + self.amplitudes.max) / 2.0,
+            amplitude_step: 0.0,
+        };
+        let mut points = vec![start; self.points];
+        let mut duration_sequence = vec![];
+        let mut amplitude_sequence = vec![];
+        for _ in 0..self.cycles {
+            for p in points.iter_mut() {
+                durations.walk(&mut p.duration, &mut p.duration_step, &mut rng);
+                self.amplitudes
+                    .walk(&mut p.amplitude, &mut p.amplitude_step, &mut rng);
+                duration_sequence.push(Duration::Samples(p.duration));
+                amplitude_sequence.push(p.amplitude);
+            }
+        }
+        (duration_sequence, amplitude_sequence)
+    }
+}
+
+/// Parses `min:max:max_step`.
+fn parse_bounds<T: Copy>(
+    parameter: &Parameter,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<[T; 3]> {
+    let bounds: Vec<T> = parameter
+        .value
+        .split(':')
+        .map(&parse)
+        .collect::<Option<_>>()
+        .ok_or_else(|| parameter.error("expected min:max:max_step"))?;
+    match bounds.as_slice() {
+        &[min, max, max_step] => Ok([min, max, max_step]),
+        _ => Err(parameter.error("expected min:max:max_step")),
+    }
+}
+
+pub(crate) fn parse_gendyn_tokens<'a>(
+    tokens: impl IntoIterator<Item = Token<'a>>,
+    default_unit: DurationUnit,
+) -> Result<Gendyn> {
+    let mut gendyn = Gendyn::default();
+    let mut duration_distribution = None;
+    let mut amplitude_distribution = None;
+    // The last of `points` and `cycles`, which an overlong product points to.
+    let mut count_parameter = None;
+    for token in tokens {
+        let parameter = Parameter::parse(token)?;
+        let distribution = || {
+            Distribution::parse(parameter.value).ok_or_else(|| {
+                parameter
+                    .error("expected a distribution such as uniform, cauchy:0.2 or logistic:0.5")
+            })
+        };
+        match parameter.name {
+            "points" => {
+                gendyn.points = parameter.count(1)?;
+                count_parameter = Some(parameter);
+            }
+            "cycles" => {
+                gendyn.cycles = parameter.count(1)?;
+                count_parameter = Some(parameter);
+            }
+            "distribution" => {
+                let d = distribution()?;
+                gendyn.durations.distribution = d;
+                gendyn.amplitudes.distribution = d;
+            }
+            "duration_distribution" => duration_distribution = Some(distribution()?),
+            "amplitude_distribution" => amplitude_distribution = Some(distribution()?),
+            "durations" => {
+                let [min, max, max_step] =
+                    parse_bounds(&parameter, |d| parse_duration(d, default_unit).ok())?;
+                let positive = |d: Duration| d.value().is_finite() && d.value() > 0.0;
+                if !(positive(min) && positive(max) && positive(max_step)) {
+                    return Err(parameter.error("expected positive durations"));
+                }
+                // Bounds in different units are compared once the sample
+                // rate is known, see `Gendyn::validate`.
+                let inverted = match (min, max) {
+                    (Duration::Samples(a), Duration::Samples(b))
+                    | (Duration::Seconds(a), Duration::Seconds(b)) => a > b,
+                    _ => false,
+                };
+                if inverted {
+                    return Err(parameter.error("expected min <= max"));
+                }
+                gendyn.durations.min = min;
+                gendyn.durations.max = max;
+                gendyn.durations.max_step = max_step;
+            }
+            "amplitudes" => {
+                let [min, max, max_step] = parse_bounds(&parameter, |a| {
+                    a.parse::<f64>().ok().filter(|a| a.is_finite())
+                })?;
+                if min > max || max_step < 0.0 {
+                    return Err(parameter.error("expected min <= max and a non-negative step"));
+                }
+                gendyn.amplitudes.min = min;
+                gendyn.amplitudes.max = max;
+                gendyn.amplitudes.max_step = max_step;
+            }
+            _ => return Err(parameter.unknown()),
+        }
+    }
+    if let (None, Some(parameter)) = (gendyn.segment_count(), count_parameter) {
+        return Err(parameter.error(&format!(
+            "points * cycles must not exceed {} segments",
+            MAX_GENERATED_LENGTH
+        )));
+    }
+    if let Some(d) = duration_distribution {
+        gendyn.durations.distribution = d;
+    }
+    if let Some(d) = amplitude_distribution {
+        gendyn.amplitudes.distribution = d;
+    }
+    Ok(gendyn)
+}
+
+/// Parses a generator such as `points=12 cycles=200 distribution=cauchy:0.2`,
+/// durations without a unit are read in `default_unit`.
+pub fn parse_gendyn(text: &str, default_unit: DurationUnit) -> Result<Gendyn> {
+    parse_gendyn_tokens(tokens(text), default_unit).map_err(|e| e.in_file(INLINE_NAME))
+}
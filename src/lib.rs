@@ -0,0 +1,30 @@
+pub mod chaos;
+pub mod duration;
+pub mod error;
+pub mod filter;
+pub mod gendyn;
+pub mod markov;
+pub mod noise;
+pub mod pitch;
+pub mod rng;
+pub mod scala;
+pub mod score;
+pub mod sequence;
+pub mod sieve;
+pub mod soundfile;
+pub mod stream;
+pub mod synth;
+pub mod wave;
+pub mod wavetable;
+
+pub use duration::{Duration, DurationUnit};
+pub use error::{Error, Result};
+pub use pitch::{midi_to_freq, parse_frequency, Tuning};
+pub use sequence::{load_floats_from_file, load_waves_from_file};
+pub use soundfile::{write_sf, write_stream, FileType, OutputSpec, SampleFormat};
+pub use stream::{ScoreStream, SegmentStream};
+pub use synth::{
+    freq_to_phase_inc, freq_to_sample_length, synthesize, Channel, LengthPolicy, Score, Voice,
+};
+pub use wave::{parse_wave, wave, Curve, Wave};
+pub use wavetable::{load_wavetable, Wavetable};
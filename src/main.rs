@@ -1,235 +1,348 @@
 use clap::Clap;
-use hound;
-use std::cmp::max;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
+use segmod3::chaos::{chaos_floats, chaos_frequencies, chaos_waves};
+use segmod3::gendyn::parse_gendyn;
+use segmod3::markov::load_chain;
+use segmod3::pitch::{parse_frequency, Tuning};
+use segmod3::scala::{load_keyboard_mapping, load_scale};
+use segmod3::score::{load_score, ScoreFile};
+use segmod3::sequence::{load_durations, load_floats, load_frequencies, load_waves};
+use segmod3::sieve::{sieve_durations, sieve_frequencies};
+use segmod3::wavetable::load_named_wavetable;
+use segmod3::{
+    parse_wave, write_stream, Channel, Duration, DurationUnit, Error, FileType, LengthPolicy,
+    OutputSpec, Result, SampleFormat, Score, ScoreStream,
+};
+use std::process;
 
 #[derive(Clap, Debug)]
 #[clap(version = "1.0", author = "Luc Döbereiner <luc.doebereiner@gmail.com>")]
 struct Opts {
-    #[clap(short, long, default_value = "output.wav")]
-    output_file: String,
-    #[clap(short, long, default_value = "48000")]
-    sample_rate: u32,
+    /// Score file, `-` reads it from standard input
+    #[clap(long)]
+    score: Option<String>,
+    /// Output file, `-` writes to standard output
     #[clap(short, long)]
-    frequencies: String,
-    #[clap(short, long)]
-    waveforms: String,
+    output_file: Option<String>,
+    /// Write a WAV file or headerless PCM
+    #[clap(long, default_value = "wav", possible_values = &["wav", "raw"])]
+    file_type: String,
     #[clap(short, long)]
+    sample_rate: Option<u32>,
+    /// Sequence options take a file, `-` for standard input or inline
+    /// values such as "440 220 330"
+    #[clap(
+        short,
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = &["sieve-frequencies", "chaos-frequencies", "markov-frequencies", "gendyn"]
+    )]
+    frequencies: Option<String>,
+    /// Frequency that ratios such as 3/2 refer to, defaults to the one of the
+    /// score or 440 Hz
+    #[clap(long)]
+    base_frequency: Option<String>,
+    /// Scala scale that resolves frequencies written as degrees such as ^3
+    #[clap(long)]
+    scale: Option<String>,
+    /// Scala keyboard mapping, degrees are then read as keys of the mapping
+    #[clap(long)]
+    kbm: Option<String>,
+    /// Segment durations, used instead of the frequencies of any source
+    #[clap(
+        short,
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = &["sieve-durations", "gendyn"]
+    )]
+    durations: Option<String>,
+    /// Unit of durations without a suffix
+    #[clap(long, default_value = "ms", possible_values = &["smp", "ms", "s"])]
+    duration_unit: String,
+    /// Generate durations and amplitudes by random walks, e.g.
+    /// "points=12 cycles=200 distribution=cauchy:0.2 durations=0.05:1:0.02"
+    #[clap(long)]
+    gendyn: Option<String>,
+    #[clap(short, long, allow_hyphen_values = true)]
+    waveforms: Option<String>,
+    /// Generate the frequencies with a Markov chain learnt from an example
+    /// or read from a transition table, e.g. "melody.txt order=2 length=200"
+    #[clap(long, allow_hyphen_values = true, conflicts_with = "gendyn")]
+    markov_frequencies: Option<String>,
+    /// Generate the waveforms with a Markov chain, see --markov-frequencies
+    #[clap(long, allow_hyphen_values = true)]
+    markov_waveforms: Option<String>,
+    /// Frequencies from the points of a Xenakis sieve, e.g.
+    /// "(8@0 | 8@3) & 5@2 base=110 step=100c"
+    #[clap(long, conflicts_with_all = &["chaos-frequencies", "markov-frequencies", "gendyn"])]
+    sieve_frequencies: Option<String>,
+    /// Durations from the distances between the points of a sieve, e.g.
+    /// "3@0 | 4@1 unit=10ms"
+    #[clap(long, conflicts_with = "gendyn")]
+    sieve_durations: Option<String>,
+    /// Frequencies from a logistic, henon or tent map, e.g.
+    /// "logistic r=3.9 length=200 range=110:880"
+    #[clap(long, conflicts_with_all = &["markov-frequencies", "gendyn"])]
+    chaos_frequencies: Option<String>,
+    /// Phase offsets from a chaotic map, in the range 0:1 by default
+    #[clap(long)]
+    chaos_phase_offsets: Option<String>,
+    /// Waveforms chosen from the waveforms by a chaotic map, e.g. "tent mu=1.9"
+    #[clap(long)]
+    chaos_waveforms: Option<String>,
+    /// Single-cycle wavetable from a WAV or text file, given as name=path or
+    /// as a path named after the file and used in waveforms as @name
+    #[clap(long)]
+    wavetable: Vec<String>,
+    #[clap(short, long, allow_hyphen_values = true)]
     phase_offsets: Option<String>,
-    #[clap(short, long, default_value = "1")]
-    breakpoints_per_cycle: u16,
-}
-
-#[derive(Debug, Clone, Copy)]
-enum Wave {
-    Sine,
-    Cosine,
-    Pulse,
-    Triangle,
-    SawUp,
-    SawDown,
-    DC(f64),
+    #[clap(short, long, allow_hyphen_values = true)]
+    amplitudes: Option<String>,
+    /// Width of each pulse segment, replaces the widths of the waveforms
+    #[clap(long, allow_hyphen_values = true)]
+    pulse_widths: Option<String>,
+    #[clap(short, long)]
+    breakpoints_per_cycle: Option<u16>,
+    /// Explicit breakpoint phases, e.g. "0.1 0.5 0.8"
+    #[clap(long, allow_hyphen_values = true)]
+    breakpoint_phases: Option<String>,
+    /// Render the shared sequences to this many channels, not allowed with a
+    /// score that declares its own channels
+    #[clap(long)]
+    channels: Option<u16>,
+    /// Rotate the sequences of each channel by this many entries more than
+    /// the previous channel
+    #[clap(long, default_value = "0")]
+    rotation_step: usize,
+    /// Number of segments: longest, shortest, lcm, a count such as 100 or a
+    /// repeat factor of the longest sequence such as 3x
+    #[clap(long)]
+    length_policy: Option<String>,
+    /// Seed of the noise and random DC segments
+    #[clap(long)]
+    seed: Option<u64>,
+    /// Use band-limited versions of all pulse and saw segments
+    #[clap(long)]
+    bandlimit: bool,
+    /// Render at a multiple of the sample rate and decimate the result
+    #[clap(long, default_value = "1", possible_values = &["1", "2", "4", "8"])]
+    oversample: u32,
+    /// Abort rendering after this many seconds of audio, 4 hours unless
+    /// --max-samples or --no-limit is given
+    #[clap(long, allow_hyphen_values = true)]
+    max_duration: Option<f64>,
+    /// Abort rendering after this many samples
+    #[clap(long)]
+    max_samples: Option<usize>,
+    /// Render without the default limit of 4 hours of audio
+    #[clap(long)]
+    no_limit: bool,
+    /// Bits per sample: 16, 24 or 32 for int, 32 or 64 for float
+    #[clap(long)]
+    bit_depth: Option<u16>,
+    #[clap(long, default_value = "int", possible_values = &["int", "float"])]
+    sample_format: String,
+    /// Disable the TPDF dither applied to 16-bit output
+    #[clap(long)]
+    no_dither: bool,
 }
 
-fn parse_wave(wave: &str) -> Wave {
-    let lc_wave = wave.to_lowercase();
-    match lc_wave.as_str() {
-        "s" => Wave::Sine,
-        "c" => Wave::Cosine,
-        "p" => Wave::Pulse,
-        "t" => Wave::Triangle,
-        "u" => Wave::SawUp,
-        "d" => Wave::SawDown,
-        _ => {
-            let dc = wave.parse::<f64>().unwrap();
-            Wave::DC(dc)
-        }
-    }
-}
+fn main() {
+    let opts: Opts = Opts::parse();
 
-fn wave(wave: Wave, cur_phase: f64, phase_offset: f64) -> f64 {
-    match wave {
-        Wave::Sine => sine(cur_phase, phase_offset),
-        Wave::Cosine => cosine(cur_phase, phase_offset),
-        Wave::Pulse => pulse(cur_phase, phase_offset),
-        Wave::Triangle => triangle(cur_phase, phase_offset),
-        Wave::SawUp => saw_up(cur_phase, phase_offset),
-        Wave::SawDown => saw_down(cur_phase, phase_offset),
-        Wave::DC(dc) => dc,
+    if let Err(e) = run(opts) {
+        eprintln!("error: {}", e);
+        process::exit(1);
     }
 }
 
-fn load_waves_from_file(file_path: &str) -> Vec<Wave> {
-    let file = File::open(file_path).expect("file wasn't found.");
-    let reader = BufReader::new(file);
-
-    let mut waves: Vec<Wave> = vec![];
-
-    reader.lines().for_each(|line| {
-        line.unwrap()
-            .split_whitespace()
-            .for_each(|w| waves.push(parse_wave(w)))
-    });
-
-    waves
-}
-
-fn load_floats_from_file(file_path: &str) -> Vec<f64> {
-    let file = File::open(file_path).expect("file wasn't found.");
-    let reader = BufReader::new(file);
-
-    let mut numbers: Vec<f64> = vec![];
-
-    reader.lines().for_each(|line| {
-        line.unwrap()
-            .split_whitespace()
-            .for_each(|e| numbers.push(e.parse::<f64>().unwrap()))
-    });
-
-    numbers
+/// The file of a generator, which is named first.
+fn generator_file(generator: &Option<String>) -> Option<&str> {
+    generator
+        .as_deref()
+        .and_then(|g| g.split_whitespace().next())
 }
 
-fn freq_to_sample_length(freq: f64, sample_rate: u32) -> f64 {
-    sample_rate as f64 / freq
-}
-
-fn freq_to_phase_inc(freq: f64, sample_rate: u32) -> f64 {
-    freq / sample_rate as f64
-}
-
-fn lin_interp(x: f64, y1: f64, y2: f64) -> f64 {
-    y1 + ((y2 - y1) * x)
-}
-
-fn sine(phase: f64, phase_offset: f64) -> f64 {
-    ((phase + phase_offset) * (std::f64::consts::PI * 2.0)).sin()
-}
-
-fn cosine(phase: f64, phase_offset: f64) -> f64 {
-    ((phase + phase_offset) * (std::f64::consts::PI * 2.0)).cos()
-}
-
-fn saw_up(phase: f64, phase_offset: f64) -> f64 {
-    let ph = fmod(phase + phase_offset, 1.0);
-    (ph * 2.0) - 1.0
-}
-
-fn saw_down(phase: f64, phase_offset: f64) -> f64 {
-    let ph = fmod(phase + phase_offset, 1.0);
-    ((ph * 2.0) - 1.0) * -1.0
-}
-
-fn fmod(numer: f64, denom: f64) -> f64 {
-    let rquot = (numer / denom).floor();
-    numer - rquot * denom
-}
-
-// fn total_length(freqs: &[f64], sample_rate: u32) -> u32 {
-//     let mut sum = 0.0;
-//     for f in freqs {
-//         sum += freq_to_sample_length(*f, sample_rate);
-//     }
-//     sum.ceil() as u32
-// }
-
-fn triangle(phase: f64, phase_offset: f64) -> f64 {
-    let ph = fmod(phase + phase_offset, 1.0);
-    if ph <= 0.25 {
-        lin_interp(ph / 0.25, 0.0, 1.0)
-    } else if ph <= 0.75 {
-        lin_interp((ph - 0.25) / 0.5, 1.0, -1.0)
-    } else {
-        lin_interp((ph - 0.75) / 0.25, -1.0, 0.0)
+fn run(opts: Opts) -> Result<()> {
+    let stdin_sources = [
+        opts.score.as_deref(),
+        opts.frequencies.as_deref(),
+        opts.durations.as_deref(),
+        opts.waveforms.as_deref(),
+        opts.phase_offsets.as_deref(),
+        opts.amplitudes.as_deref(),
+        opts.pulse_widths.as_deref(),
+        opts.breakpoint_phases.as_deref(),
+        generator_file(&opts.markov_frequencies),
+        generator_file(&opts.markov_waveforms),
+    ]
+    .iter()
+    .filter(|&&source| source == Some("-"))
+    .count();
+    if stdin_sources > 1 {
+        return Err(Error::StdinReused);
     }
-}
 
-fn pulse(phase: f64, phase_offset: f64) -> f64 {
-    let ph = phase + phase_offset;
-    if ph < 0.5 {
-        1.0
-    } else {
-        -1.0
+    // Options given on the command line override the values of the score file.
+    let ScoreFile {
+        mut score,
+        output_file,
+        mut tuning,
+    } = match &opts.score {
+        Some(source) => load_score(source)?,
+        None => ScoreFile {
+            score: Score::new(vec![], vec![]),
+            output_file: None,
+            tuning: Tuning::default(),
+        },
+    };
+    // The tuning options apply to the frequencies given on the command line.
+    if let Some(base) = &opts.base_frequency {
+        tuning.base_frequency = parse_frequency(base, &tuning)
+            .ok()
+            .filter(|f| f.is_finite() && *f > 0.0)
+            .ok_or_else(|| Error::InvalidBaseFrequency(base.clone()))?;
+    }
+    if let Some(scale) = &opts.scale {
+        tuning.scale = Some(load_scale(scale)?);
+    }
+    if let Some(mapping) = &opts.kbm {
+        tuning.keyboard_mapping = Some(load_keyboard_mapping(mapping)?);
     }
-}
-
-fn synthesize(
-    frequencies: &[f64],
-    waves: &[Wave],
-    breakpoints: u16,
-    sample_rate: u32,
-    phase_offsets: Option<&[f64]>,
-) -> Vec<f64> {
-    let ph_length = phase_offsets.map_or(0, |p| p.len());
-    let n = max(ph_length, max(frequencies.len(), waves.len()));
-    let mut output: Vec<f64> = vec![];
-    let mut cur_wave = waves[0];
-    let mut cur_phase_inc = freq_to_phase_inc(frequencies[0], sample_rate);
-    let mut cur_phase = 0.0;
-    let mut last_phase = 0.0;
-    let mut i = 0;
-    let mut phase_offset = phase_offsets.map_or(0.0, |p| p[i % p.len()]);
-
-    while i < n {
-        output.push(wave(cur_wave, cur_phase, phase_offset));
-        cur_phase += cur_phase_inc;
 
-        if (cur_phase >= 1.0) || ((breakpoints == 2) && (cur_phase >= 0.5) && (last_phase < 0.5)) {
-            i += 1;
-            cur_phase = fmod(cur_phase, 1.0);
-            cur_phase_inc = freq_to_phase_inc(frequencies[i % frequencies.len()], sample_rate);
-            cur_wave = waves[i % waves.len()];
-            last_phase = cur_phase;
-            phase_offset = phase_offsets.map_or(0.0, |p| p[i % p.len()]);
+    let duration_unit = DurationUnit::parse(&opts.duration_unit).unwrap();
+    if let Some(source) = &opts.frequencies {
+        score.frequencies = load_frequencies(source, &tuning)?;
+        score.durations = None;
+        score.gendyn = None;
+        score.frequency_chain = None;
+    }
+    if let Some(generator) = &opts.sieve_frequencies {
+        score.frequencies = sieve_frequencies(generator, tuning.base_frequency)?;
+        score.durations = None;
+        score.gendyn = None;
+        score.frequency_chain = None;
+    }
+    if let Some(generator) = &opts.chaos_frequencies {
+        score.frequencies = chaos_frequencies(generator, &tuning)?;
+        score.durations = None;
+        score.gendyn = None;
+        score.frequency_chain = None;
+    }
+    if let Some(generator) = &opts.markov_frequencies {
+        score.frequency_chain = Some(load_chain(generator, &|t| parse_frequency(t, &tuning))?);
+        score.durations = None;
+        score.gendyn = None;
+    }
+    if let Some(source) = &opts.durations {
+        score.durations = Some(load_durations(source, duration_unit)?);
+        score.gendyn = None;
+    }
+    if let Some(generator) = &opts.sieve_durations {
+        score.durations = Some(sieve_durations(generator, duration_unit)?);
+        score.gendyn = None;
+    }
+    if let Some(generator) = &opts.gendyn {
+        score.gendyn = Some(parse_gendyn(generator, duration_unit)?);
+    }
+    for table in &opts.wavetable {
+        score.wavetables.push(load_named_wavetable(table)?);
+    }
+    if let Some(source) = &opts.waveforms {
+        score.waves = load_waves(source, &score.wavetables)?;
+        score.wave_chain = None;
+    }
+    if let Some(generator) = &opts.chaos_waveforms {
+        score.waves = chaos_waves(generator, &score.waves)?;
+        score.wave_chain = None;
+    }
+    if let Some(generator) = &opts.markov_waveforms {
+        let tables = &score.wavetables;
+        score.wave_chain = Some(load_chain(generator, &|t| parse_wave(t, tables))?);
+    }
+    if let Some(source) = &opts.phase_offsets {
+        score.phase_offsets = Some(load_floats(source)?);
+    }
+    if let Some(generator) = &opts.chaos_phase_offsets {
+        score.phase_offsets = Some(chaos_floats(generator, (0.0, 1.0))?);
+    }
+    if let Some(source) = &opts.amplitudes {
+        score.amplitudes = Some(load_floats(source)?);
+    }
+    if let Some(source) = &opts.pulse_widths {
+        score.pulse_widths = Some(load_floats(source)?);
+    }
+    if let Some(sample_rate) = opts.sample_rate {
+        score.sample_rate = sample_rate;
+    }
+    if let Some(breakpoints) = opts.breakpoints_per_cycle {
+        score.breakpoints_per_cycle = breakpoints;
+    }
+    if let Some(phases) = &opts.breakpoint_phases {
+        score.breakpoint_phases = Some(load_floats(phases)?);
+    }
+    if let Some(channels) = opts.channels {
+        if !score.channels.is_empty() {
+            return Err(Error::ChannelsDeclared(score.channels.len()));
+        }
+        if channels == 0 {
+            return Err(Error::NoChannels);
         }
+        score.channels = (0..channels as usize)
+            .map(|c| Channel {
+                rotation: c * opts.rotation_step,
+                ..Channel::default()
+            })
+            .collect();
     }
-
-    output
-}
-
-fn write_sf(sample_rate: u32, output_file: String, audio: &[f64]) {
-    let amplitude = 8_388_607 as f64;
-
-    let wave_spec = hound::WavSpec {
-        channels: 1,
-        sample_rate: sample_rate,
-        bits_per_sample: 24,
-        sample_format: hound::SampleFormat::Int,
-    };
-
-    let mut writer = hound::WavWriter::create(output_file, wave_spec).unwrap();
-
-    for sample in audio.iter() {
-        writer
-            .write_sample((sample.min(1.0).max(-1.0) * amplitude) as i32)
-            .unwrap();
+    let output_file = opts
+        .output_file
+        .or(output_file)
+        .unwrap_or_else(|| String::from("output.wav"));
+
+    if let Some(policy) = &opts.length_policy {
+        score.length_policy = LengthPolicy::parse(policy)
+            .ok_or_else(|| Error::InvalidLengthPolicy(policy.clone()))?;
     }
-    writer.finalize().unwrap();
-}
+    if let Some(seed) = opts.seed {
+        score.seed = seed;
+    }
+    if opts.bandlimit {
+        score.bandlimit = true;
+    }
+    score.oversample = opts.oversample;
 
-fn main() {
-    let opts: Opts = Opts::parse();
-    let sr = opts.sample_rate;
-    let output_file = opts.output_file;
-    let frequencies = load_floats_from_file(&opts.frequencies);
-    let waves = load_waves_from_file(&opts.waveforms);
-    let phase_offsets = opts.phase_offsets.map(|file| load_floats_from_file(&file));
-    //        .as_deref();
-    // let slice = match phase_offsets {
-    //     None => None,
-    //     Some(po) => Some(po.as_slice()),
-    // };
+    // The smaller of the explicit limits replaces the default one.
+    if let Some(seconds) = opts.max_duration.filter(|s| !(s.is_finite() && *s >= 0.0)) {
+        return Err(Error::InvalidMaxDuration(seconds));
+    }
+    let limits = [
+        opts.max_duration.map(Duration::Seconds),
+        opts.max_samples.map(|n| Duration::Samples(n as f64)),
+    ];
+    let sample_rate = score.sample_rate;
+    let explicit = limits.iter().flatten().copied().min_by(|a, b| {
+        let samples = |d: &Duration| d.in_samples(sample_rate, 1);
+        samples(a).total_cmp(&samples(b))
+    });
+    if explicit.is_some() || opts.no_limit {
+        score.max_duration = explicit;
+    }
 
-    //    println!("{:?}", opts);
+    let float = opts.sample_format == "float";
+    let bits = opts.bit_depth.unwrap_or(if float { 32 } else { 24 });
+    let spec = OutputSpec {
+        format: SampleFormat::new(float, bits)?,
+        channels: score.channel_count() as u16,
+        file_type: if opts.file_type == "raw" {
+            FileType::Raw
+        } else {
+            FileType::Wav
+        },
+        dither: !opts.no_dither,
+        ..OutputSpec::new(score.sample_rate)
+    };
 
-    let audio = synthesize(
-        &frequencies,
-        &waves,
-        opts.breakpoints_per_cycle,
-        opts.sample_rate,
-        phase_offsets.as_deref(), //phase_offsets.map(|p| p.as_slice()),
-    );
+    let mut stream = ScoreStream::new(&score)?;
 
-    write_sf(sr, output_file, &audio);
+    write_stream(&spec, &output_file, &mut stream)
 }
@@ -0,0 +1,274 @@
+//! Markov chains of first or second order that generate frequency or
+//! waveform sequences. A chain is either learnt from an example sequence,
+//! which is read cyclically like any sequence, or given as a transition
+//! table with one context per line and `#` comments:
+//!
+//! ```text
+//! s -> s*2 p t      # after s: s with weight 2, p and t with weight 1
+//! p -> s
+//! t -> u:exp2 s
+//! ```
+//!
+//! A second order table has two states before the arrow. Generators are
+//! written as the file of the example or table followed by `name=value`
+//! parameters, e.g. `melody.txt order=2 length=200`.
+
+use crate::error::{Error, Result};
+use crate::rng::Rng;
+use crate::sequence::{
+    line_tokens, read_file, read_stdin, strip_comment, tokens, Parameter, Token, INLINE_NAME,
+    STDIN_NAME,
+};
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const DEFAULT_LENGTH: usize = 100;
+
+/// Mixed into the seed of the score so that the chains draw from other
+/// random numbers than the noise of the first channel.
+pub(crate) const FREQUENCY_STREAM: u64 = 0x6672_6571;
+pub(crate) const WAVE_STREAM: u64 = 0x7761_7665;
+
+/// The possible successors of each context, as state indices with weights.
+pub type Transitions = HashMap<Vec<usize>, Vec<(usize, f64)>>;
+
+#[derive(Debug, Clone)]
+pub struct MarkovChain<T> {
+    /// Number of previous states that the next one depends on, 1 or 2.
+    pub order: usize,
+    pub states: Vec<T>,
+    pub transitions: Transitions,
+    /// The first states of the generated sequence, the chain also restarts
+    /// from them when it reaches a context without successors.
+    pub start: Vec<usize>,
+    pub length: usize,
+}
+
+impl<T: Clone> MarkovChain<T> {
+    pub fn generate(&self, rng: &mut Rng) -> Vec<T> {
+        let mut sequence: Vec<usize> = vec![];
+        while sequence.len() < self.length {
+            let context = sequence
+                .len()
+                .checked_sub(self.order)
+                .map(|i| &sequence[i..]);
+            let next = context
+                .and_then(|c| self.transitions.get(c))
+                .map(|successors| choose(successors, rng));
+            match next {
+                Some(state) => sequence.push(state),
+                None => sequence.extend(&self.start),
+            }
+        }
+        sequence.truncate(self.length);
+        sequence.iter().map(|&s| self.states[s].clone()).collect()
+    }
+}
+
+fn choose(successors: &[(usize, f64)], rng: &mut Rng) -> usize {
+    let total: f64 = successors.iter().map(|(_, w)| w).sum();
+    let mut r = rng.range(0.0, total);
+    for &(state, weight) in successors {
+        if r < weight {
+            return state;
+        }
+        r -= weight;
+    }
+    successors[successors.len() - 1].0
+}
+
+/// Assigns indices to states, parsing each distinct token once.
+struct States<'p, T> {
+    names: Vec<String>,
+    values: Vec<T>,
+    parse: &'p dyn Fn(&str) -> std::result::Result<T, String>,
+}
+
+impl<'p, T> States<'p, T> {
+    fn index(&mut self, token: &Token) -> Result<usize> {
+        if let Some(i) = self.names.iter().position(|n| n == token.text) {
+            return Ok(i);
+        }
+        let value = (self.parse)(token.text).map_err(|message| token.error(&message))?;
+        self.names.push(token.text.to_string());
+        self.values.push(value);
+        Ok(self.names.len() - 1)
+    }
+}
+
+fn add_transition(transitions: &mut Transitions, context: Vec<usize>, state: usize, weight: f64) {
+    let successors = transitions.entry(context).or_default();
+    match successors.iter_mut().find(|(s, _)| *s == state) {
+        Some((_, w)) => *w += weight,
+        None => successors.push((state, weight)),
+    }
+}
+
+/// Learns the transitions of a cyclic example sequence.
+fn learn<T>(
+    tokens: &[Token],
+    order: usize,
+    states: &mut States<T>,
+) -> Result<(Transitions, Vec<usize>)> {
+    let sequence = tokens
+        .iter()
+        .map(|t| states.index(t))
+        .collect::<Result<Vec<_>>>()?;
+    let n = sequence.len();
+    let mut transitions = HashMap::new();
+    for i in 0..n {
+        let context = (0..order).map(|k| sequence[(i + k) % n]).collect();
+        add_transition(&mut transitions, context, sequence[(i + order) % n], 1.0);
+    }
+    Ok((transitions, sequence[..order.min(n)].to_vec()))
+}
+
+fn parse_successor<T>(token: &Token, states: &mut States<T>) -> Result<(usize, f64)> {
+    let (name, weight) = match token.text.rsplit_once('*') {
+        Some((name, weight)) => {
+            let weight = weight
+                .parse::<f64>()
+                .ok()
+                .filter(|w| w.is_finite() && *w > 0.0)
+                .ok_or_else(|| token.error("expected a positive weight such as s*2"))?;
+            (name, weight)
+        }
+        None => (token.text, 1.0),
+    };
+    let state = states.index(&Token {
+        text: name,
+        ..*token
+    })?;
+    Ok((state, weight))
+}
+
+/// Reads a transition table, all contexts must have the same length.
+fn parse_table<T>(
+    lines: &[Vec<Token>],
+    states: &mut States<T>,
+) -> Result<(usize, Transitions, Vec<usize>)> {
+    let mut order = None;
+    let mut start = vec![];
+    let mut transitions = HashMap::new();
+    for line in lines.iter().filter(|l| !l.is_empty()) {
+        let arrow = line
+            .iter()
+            .position(|t| t.text == "->")
+            .ok_or_else(|| line[0].error("expected a transition such as `s p -> t`"))?;
+        let (context, successors) = (&line[..arrow], &line[arrow + 1..]);
+        if context.is_empty() || successors.is_empty() {
+            return Err(line[arrow].error("expected states on both sides of the arrow"));
+        }
+        match order {
+            None if context.len() > 2 => {
+                return Err(context[2].error("only chains of order 1 or 2 are supported"))
+            }
+            None => order = Some(context.len()),
+            Some(o) if o != context.len() => {
+                return Err(line[0].error("all contexts must have the same number of states"))
+            }
+            _ => (),
+        }
+        let context = context
+            .iter()
+            .map(|t| states.index(t))
+            .collect::<Result<Vec<_>>>()?;
+        if start.is_empty() {
+            start = context.clone();
+        }
+        for token in successors {
+            let (state, weight) = parse_successor(token, states)?;
+            add_transition(&mut transitions, context.clone(), state, weight);
+        }
+    }
+    match order {
+        Some(order) => Ok((order, transitions, start)),
+        None => Err(Error::EmptySequence("transition table")),
+    }
+}
+
+/// Parses an example sequence or a transition table, see the module
+/// documentation. `order` is only used to learn from examples.
+pub fn parse_chain<T>(
+    text: &str,
+    order: usize,
+    length: usize,
+    parse: &dyn Fn(&str) -> std::result::Result<T, String>,
+) -> Result<MarkovChain<T>> {
+    let lines: Vec<Vec<Token>> = text
+        .lines()
+        .enumerate()
+        .map(|(n, line)| line_tokens(strip_comment(line), n + 1).collect())
+        .collect();
+    let mut states = States {
+        names: vec![],
+        values: vec![],
+        parse,
+    };
+    let is_table = lines.iter().flatten().any(|t| t.text == "->");
+    let (order, transitions, start) = if is_table {
+        parse_table(&lines, &mut states)?
+    } else {
+        let tokens: Vec<Token> = lines.into_iter().flatten().collect();
+        if tokens.is_empty() {
+            return Err(Error::EmptySequence("example"));
+        }
+        let (transitions, start) = learn(&tokens, order, &mut states)?;
+        (order, transitions, start)
+    };
+    Ok(MarkovChain {
+        order,
+        states: states.values,
+        transitions,
+        start,
+        length,
+    })
+}
+
+/// Loads the chain of a generator such as `melody.txt order=2 length=200`
+/// from the file `source`, which may be `-` for standard input. Relative
+/// paths refer to `directory`.
+pub(crate) fn load_chain_tokens<T>(
+    source: &Token,
+    parameters: &[Token],
+    directory: &Path,
+    parse: &dyn Fn(&str) -> std::result::Result<T, String>,
+) -> Result<MarkovChain<T>> {
+    let mut order = 1;
+    let mut length = DEFAULT_LENGTH;
+    for &token in parameters {
+        let parameter = Parameter::parse(token)?;
+        match parameter.name {
+            "order" => {
+                order = parameter
+                    .value
+                    .parse()
+                    .ok()
+                    .filter(|o| (1..=2).contains(o))
+                    .ok_or_else(|| parameter.error("expected order 1 or 2"))?
+            }
+            "length" => length = parameter.count(1)?,
+            _ => return Err(parameter.unknown()),
+        }
+    }
+    let (text, name) = if source.text == "-" {
+        (read_stdin()?, STDIN_NAME.to_string())
+    } else {
+        let file_path = directory.join(source.text).to_string_lossy().into_owned();
+        (read_file(&file_path)?, file_path)
+    };
+    parse_chain(&text, order, length, parse).map_err(|e| e.in_file(&name))
+}
+
+/// Loads the chain of a generator such as `melody.txt order=2 length=200`.
+pub fn load_chain<T>(
+    generator: &str,
+    parse: &dyn Fn(&str) -> std::result::Result<T, String>,
+) -> Result<MarkovChain<T>> {
+    let generator: Vec<Token> = tokens(generator).collect();
+    match generator.split_first() {
+        Some((source, parameters)) => load_chain_tokens(source, parameters, Path::new(""), parse)
+            .map_err(|e| e.in_file(INLINE_NAME)),
+        None => Err(Error::EmptySequence("Markov generator")),
+    }
+}
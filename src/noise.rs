@@ -0,0 +1,51 @@
+use crate::rng::Rng;
+
+/// The random state of a voice: white and pink noise and the level of the
+/// current random DC segment.
+#[derive(Debug, Clone)]
+pub struct Noise {
+    rng: Rng,
+    pink: [f64; 7],
+    level: f64,
+}
+
+impl Noise {
+    /// Every channel draws from its own stream of the seed.
+    pub fn new(seed: u64, channel: usize) -> Noise {
+        Noise {
+            rng: Rng::new(seed ^ ((channel as u64) << 32)),
+            pink: [0.0; 7],
+            level: 0.0,
+        }
+    }
+
+    /// Uniformly distributed in [-1, 1).
+    pub fn white(&mut self) -> f64 {
+        self.rng.range(-1.0, 1.0)
+    }
+
+    /// White noise filtered to a slope of -3 dB per octave (Paul Kellet's
+    /// refined method).
+    pub fn pink(&mut self) -> f64 {
+        let w = self.white();
+        let b = &mut self.pink;
+        b[0] = 0.99886 * b[0] + w * 0.0555179;
+        b[1] = 0.99332 * b[1] + w * 0.0750759;
+        b[2] = 0.96900 * b[2] + w * 0.1538520;
+        b[3] = 0.86650 * b[3] + w * 0.3104856;
+        b[4] = 0.55000 * b[4] + w * 0.5329522;
+        b[5] = -0.7616 * b[5] - w * 0.0168980;
+        let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + w * 0.5362;
+        b[6] = w * 0.115926;
+        pink * 0.11
+    }
+
+    /// Draws the level of a new random DC segment.
+    pub fn draw_level(&mut self) {
+        self.level = self.white();
+    }
+
+    pub fn level(&self) -> f64 {
+        self.level
+    }
+}
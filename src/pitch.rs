@@ -0,0 +1,188 @@
+use crate::scala::{KeyboardMapping, Scale};
+
+/// Frequency of A4, MIDI note 69.
+pub const A4_FREQUENCY: f64 = 440.0;
+
+/// Base frequency of ratios if none is given.
+pub const DEFAULT_BASE_FREQUENCY: f64 = A4_FREQUENCY;
+
+pub fn midi_to_freq(note: f64) -> f64 {
+    A4_FREQUENCY * 2f64.powf((note - 69.0) / 12.0)
+}
+
+/// MIDI note number of a note name such as `A4`, `C#3`, `Eb2` or `C-1`.
+fn parse_note_name(name: &str) -> Option<f64> {
+    let mut chars = name.chars();
+    let pitch_class = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let octave_start = rest.find(|c| c != '#' && c != 'b').unwrap_or(rest.len());
+    let (accidentals, octave) = rest.split_at(octave_start);
+    let alteration: i32 = accidentals
+        .chars()
+        .map(|c| if c == '#' { 1 } else { -1 })
+        .sum();
+    let octave: i32 = octave.parse().ok()?;
+    Some((12 * (octave + 1) + pitch_class + alteration) as f64)
+}
+
+fn parse_ratio(ratio: &str) -> Option<f64> {
+    let (numerator, denominator) = ratio.split_once('/')?;
+    let numerator: f64 = numerator.parse().ok()?;
+    let denominator: f64 = denominator.parse().ok()?;
+    Some(numerator / denominator)
+}
+
+/// Splits a trailing cents offset such as `+15c` or `-3.5c` off a token.
+fn split_cents(token: &str) -> std::result::Result<(&str, f64), String> {
+    let body = match token.strip_suffix('c') {
+        Some(body) => body,
+        None => return Ok((token, 0.0)),
+    };
+    let sign = body
+        .rfind(['+', '-'])
+        .filter(|&i| i > 0)
+        .ok_or_else(|| String::from("expected a cents offset such as +15c"))?;
+    let cents = body[sign..]
+        .parse::<f64>()
+        .map_err(|_| String::from("expected a cents offset such as +15c"))?;
+    Ok((&body[..sign], cents))
+}
+
+/// Everything needed to resolve pitches to frequencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    /// Frequency of the ratio 1/1 and, without a keyboard mapping, of
+    /// degree 0 of the scale.
+    pub base_frequency: f64,
+    pub scale: Option<Scale>,
+    pub keyboard_mapping: Option<KeyboardMapping>,
+}
+
+impl Default for Tuning {
+    fn default() -> Tuning {
+        Tuning {
+            base_frequency: DEFAULT_BASE_FREQUENCY,
+            scale: None,
+            keyboard_mapping: None,
+        }
+    }
+}
+
+impl Tuning {
+    /// Frequency of a scale degree or, with a keyboard mapping, of a key.
+    pub fn degree_frequency(&self, degree: i64) -> std::result::Result<f64, String> {
+        let scale = self
+            .scale
+            .as_ref()
+            .ok_or_else(|| String::from("scale degrees need a scale"))?;
+        match &self.keyboard_mapping {
+            Some(mapping) => mapping
+                .key_frequency(scale, degree)
+                .ok_or_else(|| String::from("the key or the reference key is not mapped")),
+            None => Ok(self.base_frequency * 2f64.powf(scale.degree_cents(degree) / 1200.0)),
+        }
+    }
+}
+
+/// Parses a frequency in Hz (`440`), a MIDI note number (`m69`), a note name
+/// (`A4`, `C#3`), a ratio of the base frequency (`3/2`) or a degree of the
+/// scale (`^3`), which is a key number if the tuning has a keyboard mapping.
+/// Each of them may be followed by an offset in cents, e.g. `A4+15c`.
+pub fn parse_frequency(token: &str, tuning: &Tuning) -> std::result::Result<f64, String> {
+    let (pitch, cents) = split_cents(token)?;
+    let frequency = if let Some(degree) = pitch.strip_prefix('^') {
+        let degree = degree
+            .parse()
+            .map_err(|_| String::from("expected a scale degree such as ^3"))?;
+        Some(tuning.degree_frequency(degree)?)
+    } else if let Some(note) = pitch.strip_prefix('m') {
+        note.parse().ok().map(midi_to_freq)
+    } else if pitch.contains('/') {
+        parse_ratio(pitch).map(|ratio| ratio * tuning.base_frequency)
+    } else if pitch.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        parse_note_name(pitch).map(midi_to_freq)
+    } else {
+        pitch.parse().ok()
+    };
+    frequency
+        .map(|f| f * 2f64.powf(cents / 1200.0))
+        .ok_or_else(|| {
+            String::from(
+                "expected a frequency in Hz, a MIDI note (m69), a note name (A4), \
+                 a ratio (3/2) or a scale degree (^3)",
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frequencies_follow_the_grammar() {
+        let tuning = Tuning::default();
+        let cases = [
+            ("440", 440.0),
+            ("27.5", 27.5),
+            ("m69", 440.0),
+            ("m60", 261.6256),
+            ("A4", 440.0),
+            ("C#3", 138.5913),
+            ("Bb2", 116.5409),
+            ("C##4", 293.6648),
+            ("Cb4", 246.9417),
+            ("E#4", 349.2282),
+            ("C-1", 8.1758),
+            ("3/2", 660.0),
+            ("A4+15c", 443.8289),
+            ("m69-15c", 436.2042),
+            ("440-3.5c", 439.1114),
+            ("3/2+1200c", 1320.0),
+        ];
+        for &(token, expected) in &cases {
+            let frequency = parse_frequency(token, &tuning).unwrap();
+            assert!(
+                (frequency - expected).abs() < 1e-3,
+                "{} is {} Hz, expected {}",
+                token,
+                frequency,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn ratios_refer_to_the_base_frequency() {
+        let tuning = Tuning {
+            base_frequency: 100.0,
+            ..Tuning::default()
+        };
+        assert_eq!(parse_frequency("3/2", &tuning), Ok(150.0));
+        assert_eq!(parse_frequency("A4", &tuning), Ok(440.0));
+    }
+
+    #[test]
+    fn malformed_frequencies_are_rejected() {
+        let tuning = Tuning::default();
+        let cases = [
+            "", "H4", "A", "Ax4", "m", "mA4", "3/", "/2", "3:2", "A4+c", "A4+15", "+15c", "A4 15c",
+            "^3", "^x",
+        ];
+        for &token in &cases {
+            assert!(
+                parse_frequency(token, &tuning).is_err(),
+                "{} should be rejected",
+                token
+            );
+        }
+    }
+}
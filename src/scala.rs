@@ -0,0 +1,321 @@
+//! Reader for Scala tuning files. A scale (`.scl`) lists the pitches of its
+//! degrees in cents or as ratios, the last one being the period that the
+//! scale repeats at. A keyboard mapping (`.kbm`) assigns scale degrees to
+//! MIDI keys and fixes the frequency of a reference key.
+
+use crate::error::{Error, Result};
+use crate::sequence::{line_tokens, read_file, Token};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scale {
+    pub description: String,
+    /// Pitches of the degrees 1 to n in cents above degree 0, the last one
+    /// is the period of the scale.
+    pub cents: Vec<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyboardMapping {
+    /// Number of keys after which the mapping repeats, 0 maps the keys
+    /// linearly to consecutive degrees.
+    pub size: usize,
+    /// Range of keys to retune. It is informational only, all keys are
+    /// tuned through the mapping.
+    pub first_key: i64,
+    pub last_key: i64,
+    /// Key that plays degree 0.
+    pub middle_key: i64,
+    pub reference_key: i64,
+    pub reference_frequency: f64,
+    /// Degree by which the scale is transposed from one repetition of the
+    /// mapping to the next.
+    pub octave_degree: usize,
+    /// Degree of each key of the mapping, `None` for unmapped keys.
+    pub keys: Vec<Option<usize>>,
+}
+
+impl Scale {
+    pub fn len(&self) -> usize {
+        self.cents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cents.is_empty()
+    }
+
+    /// Pitch of any degree in cents above degree 0, degrees outside of the
+    /// scale are transposed by whole periods.
+    pub fn degree_cents(&self, degree: i64) -> f64 {
+        let n = self.len() as i64;
+        let period = self.cents[self.len() - 1];
+        let step = degree.rem_euclid(n);
+        let step_cents = if step == 0 {
+            0.0
+        } else {
+            self.cents[step as usize - 1]
+        };
+        degree.div_euclid(n) as f64 * period + step_cents
+    }
+}
+
+impl KeyboardMapping {
+    /// Pitch of a key in cents above degree 0 of the scale, `None` if the
+    /// key is unmapped or too far from the middle key.
+    pub fn key_cents(&self, scale: &Scale, key: i64) -> Option<f64> {
+        let offset = key.checked_sub(self.middle_key)?;
+        if self.size == 0 {
+            return Some(scale.degree_cents(offset));
+        }
+        let size = self.size as i64;
+        let degree = self.keys.get(offset.rem_euclid(size) as usize).copied()??;
+        let octave_degree = match self.octave_degree {
+            0 => scale.len(),
+            d => d,
+        };
+        Some(
+            offset.div_euclid(size) as f64 * scale.degree_cents(octave_degree as i64)
+                + scale.degree_cents(degree as i64),
+        )
+    }
+
+    pub fn key_frequency(&self, scale: &Scale, key: i64) -> Option<f64> {
+        let cents = self.key_cents(scale, key)?;
+        let reference = self.key_cents(scale, self.reference_key)?;
+        Some(self.reference_frequency * 2f64.powf((cents - reference) / 1200.0))
+    }
+}
+
+/// Numbered lines that are not comments.
+fn content_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n + 1, line.trim_end_matches('\r')))
+        .filter(|(_, line)| !line.starts_with('!'))
+}
+
+fn first_token(line_number: usize, line: &str) -> Option<Token<'_>> {
+    line_tokens(line, line_number).next()
+}
+
+/// Error for a value that is missing at the end of the file, which points to
+/// its last line or the first line of an empty file.
+fn missing(text: &str, expected: &str) -> Error {
+    Token {
+        text: "",
+        line: text.lines().count().max(1),
+        column: 1,
+    }
+    .error(&format!("expected {}", expected))
+}
+
+fn parse_pitch(token: &str) -> std::result::Result<f64, String> {
+    let cents = if token.contains('.') {
+        token.parse::<f64>().ok()
+    } else {
+        let (numerator, denominator) = token.split_once('/').unwrap_or((token, "1"));
+        match (numerator.parse::<u64>(), denominator.parse::<u64>()) {
+            (Ok(n), Ok(d)) if n > 0 && d > 0 => Some(1200.0 * (n as f64 / d as f64).log2()),
+            _ => None,
+        }
+    };
+    cents
+        .filter(|c| c.is_finite())
+        .ok_or_else(|| String::from("expected a pitch in cents such as 701.955 or a ratio"))
+}
+
+pub fn parse_scale(text: &str) -> Result<Scale> {
+    let mut lines = content_lines(text);
+    let description = lines
+        .next()
+        .map_or(String::new(), |(_, line)| line.trim().to_string());
+    let mut tokens = lines.filter_map(|(n, line)| first_token(n, line));
+    let count_token = tokens
+        .next()
+        .ok_or_else(|| missing(text, "the number of notes"))?;
+    let count = count_token
+        .text
+        .parse::<usize>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| count_token.error("expected a positive number of notes"))?;
+    let mut cents = vec![];
+    for token in tokens.by_ref().take(count) {
+        cents.push(parse_pitch(token.text).map_err(|message| token.error(&message))?);
+    }
+    if cents.len() < count {
+        return Err(count_token.error("the scale has fewer notes than declared"));
+    }
+    Ok(Scale { description, cents })
+}
+
+fn integer<T: std::str::FromStr>(token: Token, expected: &str) -> Result<T> {
+    token
+        .text
+        .parse()
+        .map_err(|_| token.error(&format!("expected {}", expected)))
+}
+
+pub fn parse_keyboard_mapping(text: &str) -> Result<KeyboardMapping> {
+    let mut tokens = content_lines(text).filter_map(|(n, line)| first_token(n, line));
+    let mut next = |expected: &str| tokens.next().ok_or_else(|| missing(text, expected));
+    let size: usize = integer(next("the map size")?, "the map size")?;
+    let first_key = integer(next("the first key")?, "the first key")?;
+    let last_key = integer(next("the last key")?, "the last key")?;
+    let middle_key = integer(next("the middle key")?, "the middle key")?;
+    let reference_key = integer(next("the reference key")?, "the reference key")?;
+    let token = next("the reference frequency")?;
+    let reference_frequency = token
+        .text
+        .parse::<f64>()
+        .ok()
+        .filter(|f| f.is_finite() && *f > 0.0)
+        .ok_or_else(|| token.error("expected a positive reference frequency"))?;
+    let octave_degree = integer(next("the octave degree")?, "the octave degree")?;
+    let mut keys = vec![];
+    // Keys missing at the end of the mapping are unmapped.
+    for token in tokens.take(size) {
+        keys.push(match token.text {
+            "x" | "X" => None,
+            _ => Some(integer(token, "a scale degree or x")?),
+        });
+    }
+    Ok(KeyboardMapping {
+        size,
+        first_key,
+        last_key,
+        middle_key,
+        reference_key,
+        reference_frequency,
+        octave_degree,
+        keys,
+    })
+}
+
+pub fn load_scale(file_path: &str) -> Result<Scale> {
+    parse_scale(&read_file(file_path)?).map_err(|e| e.in_file(file_path))
+}
+
+pub fn load_keyboard_mapping(file_path: &str) -> Result<KeyboardMapping> {
+    parse_keyboard_mapping(&read_file(file_path)?).map_err(|e| e.in_file(file_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pitch::{parse_frequency, Tuning};
+
+    const PENTATONIC: &str = "\
+! pentatonic.scl
+!
+Just pentatonic
+ 5
+!
+ 9/8
+ 386.3137
+ 3/2 fifth
+ 5/3
+ 2/1
+";
+
+    /// Resolves `^degree` and checks it against `expected` Hz.
+    fn assert_degree(tuning: &Tuning, degree: i64, expected: f64) {
+        let frequency = parse_frequency(&format!("^{}", degree), tuning).unwrap();
+        assert!(
+            (frequency - expected).abs() < 1e-3,
+            "^{} is {} Hz, expected {}",
+            degree,
+            frequency,
+            expected
+        );
+    }
+
+    #[test]
+    fn scales_are_read_in_cents_and_ratios() {
+        let scale = parse_scale(PENTATONIC).unwrap();
+        assert_eq!(scale.description, "Just pentatonic");
+        assert_eq!(scale.len(), 5);
+        assert!((scale.cents[1] - 386.3137).abs() < 1e-9);
+        assert!((scale.cents[4] - 1200.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn degrees_repeat_at_the_period() {
+        let tuning = Tuning {
+            base_frequency: 100.0,
+            scale: Some(parse_scale(PENTATONIC).unwrap()),
+            keyboard_mapping: None,
+        };
+        assert_degree(&tuning, 0, 100.0);
+        assert_degree(&tuning, 1, 112.5);
+        assert_degree(&tuning, 3, 150.0);
+        assert_degree(&tuning, 5, 200.0);
+        assert_degree(&tuning, 8, 300.0);
+        assert_degree(&tuning, -1, 100.0 * 5.0 / 3.0 / 2.0);
+    }
+
+    #[test]
+    fn keyboard_mappings_fix_the_reference_key() {
+        let mapping = parse_keyboard_mapping(
+            "! six keys per octave, the third one unmapped
+6
+0
+127
+60
+69
+440.0
+5
+0
+1
+x
+2
+3
+4
+",
+        )
+        .unwrap();
+        assert_eq!(
+            mapping.keys,
+            vec![Some(0), Some(1), None, Some(2), Some(3), Some(4)]
+        );
+        let tuning = Tuning {
+            scale: Some(parse_scale(PENTATONIC).unwrap()),
+            keyboard_mapping: Some(mapping),
+            ..Tuning::default()
+        };
+        // Key 69 plays degree 2, a major third above the next period.
+        assert_degree(&tuning, 69, 440.0);
+        assert_degree(&tuning, 60, 176.0);
+        assert_degree(&tuning, 61, 198.0);
+        assert_degree(&tuning, 63, 220.0);
+        assert_degree(&tuning, 66, 352.0);
+        assert_degree(&tuning, 54, 88.0);
+        assert!(parse_frequency("^62", &tuning).is_err());
+    }
+
+    #[test]
+    fn distant_keys_are_unmapped() {
+        let mapping = parse_keyboard_mapping("0\n0\n127\n60\n69\n440\n0\n").unwrap();
+        let tuning = Tuning {
+            scale: Some(parse_scale(PENTATONIC).unwrap()),
+            keyboard_mapping: Some(mapping),
+            ..Tuning::default()
+        };
+        assert!(parse_frequency(&format!("^{}", i64::MIN), &tuning).is_err());
+    }
+
+    #[test]
+    fn empty_files_point_to_the_first_line() {
+        for error in [
+            parse_scale("").unwrap_err(),
+            parse_keyboard_mapping("").unwrap_err(),
+        ] {
+            assert!(matches!(error, Error::Parse { location, .. } if location.line == 1));
+        }
+    }
+
+    #[test]
+    fn scales_need_the_declared_notes() {
+        assert!(parse_scale("short\n3\n100.0\n200.0\n").is_err());
+        assert!(parse_scale("huge\n18446744073709551615\n100.0\n").is_err());
+    }
+}
@@ -0,0 +1,648 @@
+//! Parser for score files, which keep a whole piece in a single file:
+//!
+//! ```text
+//! # comments run to the end of the line
+//! sample_rate: 48000
+//! breakpoints: 2              # or explicit phases: breakpoint_phases: 0.1 0.5
+//! output: piece.wav
+//! frequencies: 123.123 12322
+//!              440 220        # values may continue on following lines
+//! waveforms: s s p 0.1
+//! phase: 0 0 0.1
+//! amplitudes: 1 0.5 0.25
+//! pulse_widths: 0.5 0.2        # replaces the widths of pulse segments
+//! length_policy: lcm             # longest, shortest, lcm, 100 or 3x
+//! seed: 7                        # of noise (n, pn) and random DC (r) segments
+//! ```
+//!
+//! Frequencies are given in Hz, as MIDI note numbers (`m69`), note names
+//! (`A4`, `C#3`, `Bb2`) or ratios (`3/2`) of the `base_frequency`, which
+//! defaults to 440 Hz. Any of them may be detuned in cents, e.g. `A4+15c`.
+//! With a Scala `scale` file, `^3` is the third degree of the scale above the
+//! base frequency or, if a `keyboard_mapping` (`.kbm`) is given as well, the
+//! frequency of key 3 of the mapping.
+//!
+//! `wavetables` loads single cycles from WAV or text files, given as
+//! `name=path` or as a path that is named after the file, e.g.
+//! `wavetables: vox=cycle.wav drawn.txt`. Waveforms refer to them as `@vox`
+//! or `@drawn`.
+//!
+//! Relative paths of scales, keyboard mappings, wavetables and Markov
+//! examples refer to the directory of the score file, so that a piece can be
+//! rendered from anywhere.
+//!
+//! Instead of `frequencies`, segments can be given as `durations` such as
+//! `480smp`, `12.5ms` or `0.1s`; numbers without a unit are read in the
+//! `duration_unit` (`smp`, `ms` or `s`, default `ms`). Durations take
+//! precedence over frequencies. Only one source of frequencies
+//! (`frequencies`, `sieve_frequencies`, `chaos_frequencies`,
+//! `markov_frequencies`) and one of durations (`durations`,
+//! `sieve_durations`) may be given, and none of them together with `gendyn`.
+//!
+//! `frequencies` and `waveforms` are required, all other keys are optional.
+//!
+//! `gendyn` generates the durations and amplitudes by random walks instead,
+//! e.g. `gendyn: points=12 cycles=200 distribution=cauchy:0.2`, see
+//! [`crate::gendyn`].
+//!
+//! `markov_frequencies` and `markov_waveforms` generate the frequencies or
+//! waveforms with a Markov chain, e.g. `markov_frequencies: melody.txt
+//! order=2 length=200`, see [`crate::markov`].
+//!
+//! `sieve_frequencies` and `sieve_durations` take the points of a Xenakis
+//! sieve instead, e.g. `sieve_frequencies: (8@0 | 8@3) & 5@2 base=110
+//! step=100c` or `sieve_durations: 3@0 | 4@1 unit=10ms`, see
+//! [`crate::sieve`].
+//!
+//! `chaos_frequencies`, `chaos_phases` and `chaos_waveforms` iterate a
+//! logistic, Hénon or tent map, e.g. `chaos_frequencies: logistic r=3.9
+//! range=A2:A5`. The chaotic waveforms are chosen from the `waveforms`, see
+//! [`crate::chaos`].
+//!
+//! Multichannel scores declare the number of `channels`. The sequence keys
+//! take a 1-based channel suffix to give a channel its own sequence, e.g.
+//! `frequencies.2:`, and `rotation:` lists for every channel the number of
+//! entries by which its sequences are rotated.
+
+use crate::chaos::{chaos_float_tokens, chaos_frequency_tokens, chaos_wave_tokens};
+use crate::duration::{parse_duration, DurationUnit};
+use crate::error::{Error, Result};
+use crate::gendyn::parse_gendyn_tokens;
+use crate::markov::{load_chain_tokens, MarkovChain};
+use crate::pitch::{parse_frequency, Tuning};
+use crate::scala::{load_keyboard_mapping, load_scale};
+use crate::sequence::{
+    line_tokens, parse_float, parse_tokens, read_file, read_stdin, strip_comment, Token, STDIN_NAME,
+};
+use crate::sieve::{sieve_duration_tokens, sieve_frequency_tokens};
+use crate::synth::{Channel, LengthPolicy, Score};
+use crate::wave::parse_wave;
+use crate::wavetable::load_named_wavetable_in;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct ScoreFile {
+    pub score: Score,
+    pub output_file: Option<String>,
+    /// The tuning of the frequencies in the score.
+    pub tuning: Tuning,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Key {
+    Frequencies,
+    BaseFrequency,
+    Scale,
+    KeyboardMapping,
+    Durations,
+    DurationUnit,
+    Gendyn,
+    MarkovFrequencies,
+    MarkovWaveforms,
+    SieveFrequencies,
+    SieveDurations,
+    ChaosFrequencies,
+    ChaosPhases,
+    ChaosWaveforms,
+    Waveforms,
+    Wavetables,
+    Phase,
+    Amplitudes,
+    PulseWidths,
+    SampleRate,
+    Breakpoints,
+    BreakpointPhases,
+    Output,
+    Channels,
+    Rotation,
+    LengthPolicy,
+    Seed,
+}
+
+/// The keys that give the frequencies or durations of the segments. Only one
+/// source of each may be given, durations take precedence over frequencies
+/// and `gendyn` replaces both.
+const TIMING_SOURCES: [Key; 7] = [
+    Key::Frequencies,
+    Key::SieveFrequencies,
+    Key::ChaosFrequencies,
+    Key::MarkovFrequencies,
+    Key::Durations,
+    Key::SieveDurations,
+    Key::Gendyn,
+];
+
+fn is_duration(key: Key) -> bool {
+    matches!(key, Key::Durations | Key::SieveDurations)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    match name.to_lowercase().as_str() {
+        "frequencies" => Some(Key::Frequencies),
+        "base_frequency" => Some(Key::BaseFrequency),
+        "scale" => Some(Key::Scale),
+        "keyboard_mapping" | "kbm" => Some(Key::KeyboardMapping),
+        "durations" => Some(Key::Durations),
+        "duration_unit" => Some(Key::DurationUnit),
+        "gendyn" => Some(Key::Gendyn),
+        "markov_frequencies" => Some(Key::MarkovFrequencies),
+        "markov_waveforms" => Some(Key::MarkovWaveforms),
+        "sieve_frequencies" => Some(Key::SieveFrequencies),
+        "sieve_durations" => Some(Key::SieveDurations),
+        "chaos_frequencies" => Some(Key::ChaosFrequencies),
+        "chaos_phases" | "chaos_phase_offsets" => Some(Key::ChaosPhases),
+        "chaos_waveforms" => Some(Key::ChaosWaveforms),
+        "waveforms" => Some(Key::Waveforms),
+        "wavetables" => Some(Key::Wavetables),
+        "phase" | "phases" | "phase_offsets" => Some(Key::Phase),
+        "amplitudes" => Some(Key::Amplitudes),
+        "pulse_widths" | "widths" => Some(Key::PulseWidths),
+        "sample_rate" => Some(Key::SampleRate),
+        "breakpoints" | "breakpoints_per_cycle" => Some(Key::Breakpoints),
+        "breakpoint_phases" => Some(Key::BreakpointPhases),
+        "output" | "output_file" => Some(Key::Output),
+        "channels" => Some(Key::Channels),
+        "rotation" => Some(Key::Rotation),
+        "length_policy" => Some(Key::LengthPolicy),
+        "seed" => Some(Key::Seed),
+        _ => None,
+    }
+}
+
+/// Returns the key name and the byte offset of its value if the line starts
+/// with `identifier:` followed by whitespace or the end of the line. Tokens
+/// such as `u:exp2` are values.
+fn split_key(line: &str) -> Option<(&str, usize)> {
+    let colon = line.find(':')?;
+    let name = line[..colon].trim();
+    let rest = &line[colon + 1..];
+    let is_ident = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        && !name.starts_with(|c: char| c.is_ascii_digit());
+    if is_ident && (rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        Some((name, colon + 1))
+    } else {
+        None
+    }
+}
+
+fn single<'a>(key: &Token, tokens: &[Token<'a>]) -> Result<Token<'a>> {
+    match tokens {
+        [token] => Ok(*token),
+        [] => Err(key.error("expected a value")),
+        [_, extra, ..] => Err(extra.error("expected a single value")),
+    }
+}
+
+/// Splits `frequencies.2` into the key and the 0-based channel index.
+fn parse_channel_key(key_token: &Token) -> Result<(Key, Option<usize>)> {
+    let (name, channel) = match key_token.text.split_once('.') {
+        Some((name, channel)) => (name, Some(channel)),
+        None => (key_token.text, None),
+    };
+    let key = parse_key(name).ok_or_else(|| key_token.error("unknown key"))?;
+    match channel {
+        None => Ok((key, None)),
+        Some(channel) => {
+            if !matches!(
+                key,
+                Key::Frequencies
+                    | Key::Durations
+                    | Key::Waveforms
+                    | Key::Phase
+                    | Key::Amplitudes
+                    | Key::PulseWidths
+            ) {
+                return Err(key_token.error("only sequences can be given per channel"));
+            }
+            match channel.parse::<usize>() {
+                Ok(c) if c > 0 => Ok((key, Some(c - 1))),
+                _ => Err(key_token.error("expected a channel number starting at 1")),
+            }
+        }
+    }
+}
+
+/// Loads a Markov chain from `file order=2 length=200`.
+fn chain<T>(
+    key: &Token,
+    tokens: &[Token],
+    directory: &Path,
+    parse: &dyn Fn(&str) -> std::result::Result<T, String>,
+) -> Result<MarkovChain<T>> {
+    match tokens {
+        [source, parameters @ ..] => load_chain_tokens(source, parameters, directory, parse),
+        [] => Err(key.error("expected a file and parameters")),
+    }
+}
+
+/// Generates a sequence from a sieve or a chaotic map and its parameters.
+fn generated<T>(
+    key: &Token,
+    tokens: &[Token],
+    generate: impl Fn(&[Token]) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    match tokens {
+        [] => Err(key.error("expected a generator and parameters")),
+        tokens => generate(tokens),
+    }
+}
+
+type Entry<'a> = ((Key, Option<usize>), Token<'a>, Vec<Token<'a>>);
+
+/// Parses a score whose relative paths refer to the current directory.
+pub fn parse_score(text: &str) -> Result<ScoreFile> {
+    parse_score_in(text, Path::new(""))
+}
+
+/// Parses a score whose relative paths, of scales, keyboard mappings,
+/// wavetables and Markov examples, refer to `directory`.
+pub fn parse_score_in(text: &str, directory: &Path) -> Result<ScoreFile> {
+    let resolve = |token: Token| directory.join(token.text).to_string_lossy().into_owned();
+    let mut entries: Vec<Entry> = vec![];
+
+    for (n, line) in text.lines().enumerate() {
+        let line = strip_comment(line);
+        match split_key(line) {
+            Some((name, value_start)) => {
+                let key_token = line_tokens(line, n + 1).next().unwrap();
+                let key_token = Token {
+                    text: name,
+                    ..key_token
+                };
+                let key = parse_channel_key(&key_token)?;
+                if entries.iter().any(|(k, _, _)| *k == key) {
+                    return Err(key_token.error("duplicate key"));
+                }
+                let offset = line[..value_start].chars().count();
+                let values = line_tokens(&line[value_start..], n + 1)
+                    .map(|t| Token {
+                        column: t.column + offset,
+                        ..t
+                    })
+                    .collect();
+                entries.push((key, key_token, values));
+            }
+            None => {
+                let mut values = line_tokens(line, n + 1).peekable();
+                if let Some(first) = values.peek() {
+                    match entries.last_mut() {
+                        Some((_, _, tokens)) => tokens.extend(values),
+                        None => return Err(first.error("value without a key")),
+                    }
+                }
+            }
+        }
+    }
+
+    let channel_entry = |key: Key, channel: Option<usize>| {
+        entries
+            .iter()
+            .find(|(k, _, _)| *k == (key, channel))
+            .map(|(_, key_token, tokens)| (key_token, tokens.as_slice()))
+    };
+    let entry = |key: Key| channel_entry(key, None);
+    let missing = |key: &'static str| Error::MissingKey { path: None, key };
+
+    let mut channel_count = entries
+        .iter()
+        .filter_map(|((_, channel), _, _)| channel.map(|c| c + 1))
+        .max()
+        .unwrap_or(0);
+    if let Some((key, tokens)) = entry(Key::Channels) {
+        let token = single(key, tokens)?;
+        let count = token
+            .text
+            .parse::<usize>()
+            .ok()
+            .filter(|&c| c > 0 && c >= channel_count && c <= u16::MAX as usize)
+            .ok_or_else(|| {
+                token.error("expected a number of channels covering all channel keys")
+            })?;
+        channel_count = count;
+    }
+
+    let duration_unit = match entry(Key::DurationUnit) {
+        Some((key, tokens)) => {
+            let token = single(key, tokens)?;
+            DurationUnit::parse(token.text)
+                .ok_or_else(|| token.error("expected a duration unit (smp, ms, s)"))?
+        }
+        None => DurationUnit::Milliseconds,
+    };
+    let parse_durations = |tokens: &[Token]| {
+        parse_tokens(tokens.iter().copied(), |t| parse_duration(t, duration_unit))
+    };
+
+    let mut tuning = Tuning::default();
+    if let Some((key, tokens)) = entry(Key::BaseFrequency) {
+        let token = single(key, tokens)?;
+        tuning.base_frequency = parse_frequency(token.text, &tuning)
+            .ok()
+            .filter(|f| f.is_finite() && *f > 0.0)
+            .ok_or_else(|| token.error("expected a positive base frequency"))?;
+    }
+    if let Some((key, tokens)) = entry(Key::Scale) {
+        tuning.scale = Some(load_scale(&resolve(single(key, tokens)?))?);
+    }
+    if let Some((key, tokens)) = entry(Key::KeyboardMapping) {
+        tuning.keyboard_mapping = Some(load_keyboard_mapping(&resolve(single(key, tokens)?))?);
+    }
+    let parse_frequencies =
+        |tokens: &[Token]| parse_tokens(tokens.iter().copied(), |t| parse_frequency(t, &tuning));
+
+    let wavetables = match entry(Key::Wavetables) {
+        Some((_, tokens)) => tokens
+            .iter()
+            .map(|t| load_named_wavetable_in(t.text, directory))
+            .collect::<Result<Vec<_>>>()?,
+        None => vec![],
+    };
+    let parse_waves =
+        |tokens: &[Token]| parse_tokens(tokens.iter().copied(), |t| parse_wave(t, &wavetables));
+
+    let mut channels = vec![Channel::default(); channel_count];
+    for (c, channel) in channels.iter_mut().enumerate() {
+        if let Some((_, tokens)) = channel_entry(Key::Frequencies, Some(c)) {
+            channel.frequencies = Some(parse_frequencies(tokens)?);
+        }
+        if let Some((_, tokens)) = channel_entry(Key::Durations, Some(c)) {
+            channel.durations = Some(parse_durations(tokens)?);
+        }
+        if let Some((_, tokens)) = channel_entry(Key::Waveforms, Some(c)) {
+            channel.waves = Some(parse_waves(tokens)?);
+        }
+        if let Some((_, tokens)) = channel_entry(Key::Phase, Some(c)) {
+            channel.phase_offsets = Some(parse_tokens(tokens.iter().copied(), parse_float)?);
+        }
+        if let Some((_, tokens)) = channel_entry(Key::Amplitudes, Some(c)) {
+            channel.amplitudes = Some(parse_tokens(tokens.iter().copied(), parse_float)?);
+        }
+        if let Some((_, tokens)) = channel_entry(Key::PulseWidths, Some(c)) {
+            channel.pulse_widths = Some(parse_tokens(tokens.iter().copied(), parse_float)?);
+        }
+    }
+    if let Some((key, tokens)) = entry(Key::Rotation) {
+        if tokens.len() != channels.len() {
+            return Err(key.error("expected one rotation per channel"));
+        }
+        let rotations = parse_tokens(tokens.iter().copied(), |t| {
+            t.parse::<usize>()
+                .map_err(|_| String::from("expected a non-negative integer"))
+        })?;
+        for (channel, rotation) in channels.iter_mut().zip(rotations) {
+            channel.rotation = rotation;
+        }
+    }
+
+    // Sources of the same sequence would silently replace each other.
+    let conflict = |a: Key, b: Key| match (entry(a), entry(b)) {
+        (Some((a, _)), Some((b, _))) => {
+            let (first, second) = if (a.line, a.column) < (b.line, b.column) {
+                (a, b)
+            } else {
+                (b, a)
+            };
+            Err(second.error(&format!("cannot be combined with `{}`", first.text)))
+        }
+        _ => Ok(()),
+    };
+    for (i, &a) in TIMING_SOURCES.iter().enumerate() {
+        for &b in &TIMING_SOURCES[i + 1..] {
+            if a == Key::Gendyn || b == Key::Gendyn || is_duration(a) == is_duration(b) {
+                conflict(a, b)?;
+            }
+        }
+    }
+
+    // The shared sequences may only be left out if they are replaced by one
+    // of the `alternatives` or every channel has its own.
+    let shared = |key: Key, alternatives: &[Key], name: &'static str| {
+        let keys = || std::iter::once(key).chain(alternatives.iter().copied());
+        match entry(key) {
+            Some((_, tokens)) => Ok(Some(tokens)),
+            None if alternatives.iter().any(|&k| entry(k).is_some()) => Ok(None),
+            None if channel_count > 0
+                && (0..channel_count)
+                    .all(|c| keys().any(|k| channel_entry(k, Some(c)).is_some())) =>
+            {
+                Ok(None)
+            }
+            None => Err(missing(name)),
+        }
+    };
+    let frequencies = match shared(
+        Key::Frequencies,
+        &[
+            Key::Durations,
+            Key::Gendyn,
+            Key::MarkovFrequencies,
+            Key::SieveFrequencies,
+            Key::SieveDurations,
+            Key::ChaosFrequencies,
+        ],
+        "frequencies",
+    )? {
+        Some(tokens) => parse_frequencies(tokens)?,
+        None => vec![],
+    };
+    let waves = match shared(Key::Waveforms, &[Key::MarkovWaveforms], "waveforms")? {
+        Some(tokens) => parse_waves(tokens)?,
+        None => vec![],
+    };
+
+    let mut score = Score::new(frequencies, waves);
+    score.channels = channels;
+    score.wavetables = wavetables;
+    if let Some((_, durations)) = entry(Key::Durations) {
+        score.durations = Some(parse_durations(durations)?);
+    }
+    if let Some((key, tokens)) = entry(Key::SieveFrequencies) {
+        score.frequencies = generated(key, tokens, |t| {
+            sieve_frequency_tokens(t, tuning.base_frequency)
+        })?;
+    }
+    if let Some((key, tokens)) = entry(Key::SieveDurations) {
+        score.durations = Some(generated(key, tokens, |t| {
+            sieve_duration_tokens(t, duration_unit)
+        })?);
+    }
+    if let Some((key, tokens)) = entry(Key::ChaosFrequencies) {
+        score.frequencies = generated(key, tokens, |t| chaos_frequency_tokens(t, &tuning))?;
+    }
+    if let Some((key, tokens)) = entry(Key::ChaosWaveforms) {
+        score.waves = generated(key, tokens, |t| chaos_wave_tokens(t, &score.waves))?;
+    }
+    if let Some((key, tokens)) = entry(Key::MarkovFrequencies) {
+        score.frequency_chain = Some(chain(key, tokens, directory, &|t| {
+            parse_frequency(t, &tuning)
+        })?);
+    }
+    if let Some((key, tokens)) = entry(Key::MarkovWaveforms) {
+        score.wave_chain = Some(chain(key, tokens, directory, &|t| {
+            parse_wave(t, &score.wavetables)
+        })?);
+    }
+    if let Some((_, tokens)) = entry(Key::Gendyn) {
+        score.gendyn = Some(parse_gendyn_tokens(tokens.iter().copied(), duration_unit)?);
+    }
+    if let Some((_, phases)) = entry(Key::Phase) {
+        score.phase_offsets = Some(parse_tokens(phases.iter().copied(), parse_float)?);
+    }
+    if let Some((key, tokens)) = entry(Key::ChaosPhases) {
+        score.phase_offsets = Some(generated(key, tokens, |t| {
+            chaos_float_tokens(t, (0.0, 1.0))
+        })?);
+    }
+    if let Some((_, amplitudes)) = entry(Key::Amplitudes) {
+        score.amplitudes = Some(parse_tokens(amplitudes.iter().copied(), parse_float)?);
+    }
+    if let Some((_, widths)) = entry(Key::PulseWidths) {
+        score.pulse_widths = Some(parse_tokens(widths.iter().copied(), parse_float)?);
+    }
+    if let Some((key, tokens)) = entry(Key::SampleRate) {
+        let token = single(key, tokens)?;
+        score.sample_rate = token
+            .text
+            .parse()
+            .map_err(|_| token.error("expected a sample rate in Hz"))?;
+    }
+    if let Some((key, tokens)) = entry(Key::Breakpoints) {
+        let token = single(key, tokens)?;
+        score.breakpoints_per_cycle = token
+            .text
+            .parse()
+            .map_err(|_| token.error("expected a number of breakpoints"))?;
+    }
+    if let Some((key, tokens)) = entry(Key::LengthPolicy) {
+        let token = single(key, tokens)?;
+        score.length_policy = LengthPolicy::parse(token.text).ok_or_else(|| {
+            token.error(
+                "expected longest, shortest, lcm, a positive segment count or a repeat factor",
+            )
+        })?;
+    }
+    if let Some((key, tokens)) = entry(Key::Seed) {
+        let token = single(key, tokens)?;
+        score.seed = token
+            .text
+            .parse()
+            .map_err(|_| token.error("expected a non-negative integer seed"))?;
+    }
+    if let Some((_, phases)) = entry(Key::BreakpointPhases) {
+        score.breakpoint_phases = Some(parse_tokens(phases.iter().copied(), parse_float)?);
+    }
+    let output_file = match entry(Key::Output) {
+        Some((key, tokens)) => Some(single(key, tokens)?.text.to_string()),
+        None => None,
+    };
+
+    Ok(ScoreFile {
+        score,
+        output_file,
+        tuning,
+    })
+}
+
+/// Loads a score, relative paths in it refer to the directory of the score.
+pub fn load_score_from_file(file_path: &str) -> Result<ScoreFile> {
+    let directory = Path::new(file_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    parse_score_in(&read_file(file_path)?, directory).map_err(|e| e.in_file(file_path))
+}
+
+/// Loads a score from a file or, if `source` is `-`, from standard input.
+pub fn load_score(source: &str) -> Result<ScoreFile> {
+    if source == "-" {
+        parse_score(&read_stdin()?).map_err(|e| e.in_file(STDIN_NAME))
+    } else {
+        load_score_from_file(source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::Duration;
+    use crate::pitch::midi_to_freq;
+    use crate::wave::Wave;
+
+    #[test]
+    fn values_continue_on_following_lines() {
+        let text = "\
+# a score
+sample_rate: 8000   # per second
+frequencies: 100 200
+    300
+      # a comment between the values
+    400
+waveforms: s
+    t
+";
+        let score = parse_score(text).unwrap().score;
+        assert_eq!(score.sample_rate, 8000);
+        assert_eq!(score.frequencies, vec![100.0, 200.0, 300.0, 400.0]);
+        assert!(matches!(score.waves[..], [Wave::Sine, Wave::Triangle(..)]));
+    }
+
+    #[test]
+    fn note_names_are_not_comments() {
+        let score = parse_score("frequencies: C#3 #A4\nwaveforms: s")
+            .unwrap()
+            .score;
+        assert_eq!(score.frequencies, vec![midi_to_freq(49.0)]);
+    }
+
+    #[test]
+    fn values_without_a_key_are_rejected() {
+        match parse_score("# header\n  440 220\nwaveforms: s") {
+            Err(Error::Parse { location, .. }) => {
+                assert_eq!((location.line, location.column), (2, 3))
+            }
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn timing_sources_are_exclusive() {
+        let text = "frequencies: 1\nwaveforms: s\nsieve_frequencies: 3@0";
+        match parse_score(text) {
+            Err(Error::Parse {
+                location, token, ..
+            }) => assert_eq!((location.line, token.as_str()), (3, "sieve_frequencies")),
+            result => panic!("unexpected {:?}", result.map(|_| ())),
+        }
+        assert!(parse_score("durations: 1\nwaveforms: s\nsieve_durations: 3@0").is_err());
+        assert!(parse_score("gendyn: points=2\nwaveforms: s\ndurations: 1").is_err());
+        let score = parse_score("frequencies: 1\ndurations: 2smp\nwaveforms: s")
+            .unwrap()
+            .score;
+        assert_eq!(score.durations, Some(vec![Duration::Samples(2.0)]));
+    }
+
+    #[test]
+    fn paths_refer_to_the_directory_of_the_score() {
+        let dir = std::env::temp_dir().join(format!("segmod3-score-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("fifths.scl"), "fifths\n2\n3/2\n2/1\n").unwrap();
+        std::fs::write(dir.join("cycle.txt"), "0 1 0 -1").unwrap();
+        let score_path = dir.join("piece.score");
+        std::fs::write(
+            &score_path,
+            "scale: fifths.scl\nwavetables: cycle.txt\nfrequencies: ^1\nwaveforms: @cycle",
+        )
+        .unwrap();
+        let score = load_score_from_file(score_path.to_str().unwrap()).unwrap();
+        assert!((score.score.frequencies[0] - 660.0).abs() < 1e-9);
+        assert_eq!(score.score.wavetables[0].name, "cycle");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        assert!(parse_score("frequencies: 1\nwaveforms: s\nfrequencies: 2").is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use crate::duration::{parse_duration, Duration, DurationUnit};
+use crate::error::{Error, Location, Result};
+use crate::pitch::{parse_frequency, Tuning};
+use crate::wave::{parse_wave, Wave};
+use crate::wavetable::Wavetable;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// A whitespace separated token together with its position in the text.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Token<'a> {
+    pub text: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn error(&self, message: &str) -> Error {
+        Error::Parse {
+            location: Location {
+                path: None,
+                line: self.line,
+                column: self.column,
+            },
+            token: self.text.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Splits a single line into tokens, `line_number` being its 1-based position.
+pub(crate) fn line_tokens(line: &str, line_number: usize) -> impl Iterator<Item = Token<'_>> {
+    let mut start = None;
+    line.char_indices()
+        .chain(std::iter::once((line.len(), ' ')))
+        .filter_map(move |(i, c)| match (start, c.is_whitespace()) {
+            (None, false) => {
+                start = Some(i);
+                None
+            }
+            (Some(s), true) => {
+                start = None;
+                Some(Token {
+                    text: &line[s..i],
+                    line: line_number,
+                    column: line[..s].chars().count() + 1,
+                })
+            }
+            _ => None,
+        })
+}
+
+/// Removes a comment, which starts with a `#` at the beginning of a token
+/// so that note names such as `C#3` are kept.
+pub(crate) fn strip_comment(line: &str) -> &str {
+    let comment = line
+        .char_indices()
+        .find(|&(i, c)| c == '#' && line[..i].chars().last().is_none_or(char::is_whitespace));
+    match comment {
+        Some((i, _)) => &line[..i],
+        None => line,
+    }
+}
+
+pub(crate) fn tokens(text: &str) -> impl Iterator<Item = Token<'_>> {
+    text.lines()
+        .enumerate()
+        .flat_map(|(n, line)| line_tokens(line, n + 1))
+}
+
+pub(crate) fn parse_tokens<'a, T>(
+    tokens: impl IntoIterator<Item = Token<'a>>,
+    parse: impl Fn(&str) -> std::result::Result<T, String>,
+) -> Result<Vec<T>> {
+    tokens
+        .into_iter()
+        .map(|token| parse(token.text).map_err(|message| token.error(&message)))
+        .collect()
+}
+
+pub fn parse_float(token: &str) -> std::result::Result<f64, String> {
+    token
+        .parse::<f64>()
+        .map_err(|_| String::from("expected a number"))
+}
+
+/// Parses waveforms, `@name` refers to one of the `tables`.
+pub fn parse_waves(text: &str, tables: &[Wavetable]) -> Result<Vec<Wave>> {
+    parse_tokens(tokens(text), |t| parse_wave(t, tables))
+}
+
+pub fn parse_floats(text: &str) -> Result<Vec<f64>> {
+    parse_tokens(tokens(text), parse_float)
+}
+
+/// Parses frequencies, ratios and scale degrees are resolved through `tuning`.
+pub fn parse_frequencies(text: &str, tuning: &Tuning) -> Result<Vec<f64>> {
+    parse_tokens(tokens(text), |t| parse_frequency(t, tuning))
+}
+
+pub fn parse_durations(text: &str, default_unit: DurationUnit) -> Result<Vec<Duration>> {
+    parse_tokens(tokens(text), |t| parse_duration(t, default_unit))
+}
+
+pub(crate) fn read_file(file_path: &str) -> Result<String> {
+    fs::read_to_string(file_path).map_err(|e| Error::io(file_path, e))
+}
+
+/// Name used in error messages for values read from standard input.
+pub(crate) const STDIN_NAME: &str = "<stdin>";
+/// Name used in error messages for values given inline.
+pub(crate) const INLINE_NAME: &str = "<inline>";
+
+/// Most values that a generator may produce, larger lengths are rejected
+/// while parsing rather than exhausting memory.
+pub const MAX_GENERATED_LENGTH: usize = 1 << 24;
+
+/// A `name=value` parameter of a generator, errors point to its token.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Parameter<'a> {
+    pub name: &'a str,
+    pub value: &'a str,
+    pub token: Token<'a>,
+}
+
+impl<'a> Parameter<'a> {
+    pub fn parse(token: Token<'a>) -> Result<Parameter<'a>> {
+        let (name, value) = token
+            .text
+            .split_once('=')
+            .ok_or_else(|| token.error("expected name=value"))?;
+        Ok(Parameter { name, value, token })
+    }
+
+    pub fn error(&self, message: &str) -> Error {
+        self.token.error(message)
+    }
+
+    /// The error of parameters that the generator does not take.
+    pub fn unknown(&self) -> Error {
+        self.error("unknown generator parameter")
+    }
+
+    pub fn number(&self) -> Result<f64> {
+        self.value
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| self.error("expected a number"))
+    }
+
+    pub fn integer(&self) -> Result<i64> {
+        self.value
+            .parse()
+            .map_err(|_| self.error("expected an integer"))
+    }
+
+    /// A count of at least `min` and at most `MAX_GENERATED_LENGTH`.
+    pub fn count(&self, min: usize) -> Result<usize> {
+        self.value
+            .parse()
+            .ok()
+            .filter(|n| (min..=MAX_GENERATED_LENGTH).contains(n))
+            .ok_or_else(|| {
+                self.error(&format!(
+                    "expected an integer from {} to {}",
+                    min, MAX_GENERATED_LENGTH
+                ))
+            })
+    }
+}
+
+pub(crate) fn read_stdin() -> Result<String> {
+    let mut text = String::new();
+    io::stdin()
+        .read_to_string(&mut text)
+        .map_err(|e| Error::io(STDIN_NAME, e))?;
+    Ok(text)
+}
+
+/// Reads a sequence from `source`, which is either `-` for standard input,
+/// the path of an existing file or the values themselves, e.g. `440 220 330`.
+pub fn load_sequence<T>(source: &str, parse: impl Fn(&str) -> Result<Vec<T>>) -> Result<Vec<T>> {
+    if source == "-" {
+        return parse(&read_stdin()?).map_err(|e| e.in_file(STDIN_NAME));
+    }
+    if Path::new(source).is_file() {
+        return parse(&read_file(source)?).map_err(|e| e.in_file(source));
+    }
+    match parse(source) {
+        Ok(values) => Ok(values),
+        // A single word that is not a valid value and looks like a path most
+        // likely is a misspelt file name.
+        Err(e) if looks_like_path(source) => match read_file(source) {
+            Err(io_error) => Err(io_error),
+            Ok(_) => Err(e.in_file(INLINE_NAME)),
+        },
+        Err(e) => Err(e.in_file(INLINE_NAME)),
+    }
+}
+
+fn looks_like_path(source: &str) -> bool {
+    let source = source.trim();
+    let has_extension = Path::new(source)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.chars().all(|c| c.is_ascii_alphabetic()));
+    !source.contains(char::is_whitespace)
+        && (has_extension || source.contains(std::path::is_separator))
+}
+
+pub fn load_waves(source: &str, tables: &[Wavetable]) -> Result<Vec<Wave>> {
+    load_sequence(source, |text| parse_waves(text, tables))
+}
+
+pub fn load_floats(source: &str) -> Result<Vec<f64>> {
+    load_sequence(source, parse_floats)
+}
+
+pub fn load_frequencies(source: &str, tuning: &Tuning) -> Result<Vec<f64>> {
+    load_sequence(source, |text| parse_frequencies(text, tuning))
+}
+
+pub fn load_durations(source: &str, default_unit: DurationUnit) -> Result<Vec<Duration>> {
+    load_sequence(source, |text| parse_durations(text, default_unit))
+}
+
+pub fn load_waves_from_file(file_path: &str) -> Result<Vec<Wave>> {
+    parse_waves(&read_file(file_path)?, &[]).map_err(|e| e.in_file(file_path))
+}
+
+pub fn load_floats_from_file(file_path: &str) -> Result<Vec<f64>> {
+    parse_floats(&read_file(file_path)?).map_err(|e| e.in_file(file_path))
+}
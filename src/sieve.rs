@@ -0,0 +1,449 @@
+//! Xenakis sieves: sets of integers built from residue classes `m@r`, the
+//! integers `n` with `n mod m = r`, combined by union `|`, intersection `&`
+//! and complement `~`, e.g. `(8@0 | 8@3) & ~5@2`. `~` binds tightest, then
+//! `&`, then `|`.
+//!
+//! A generator is a sieve followed by `name=value` parameters. The points
+//! are taken from `from` (default 0) up to `to` (exclusive) or until there
+//! are `count` of them, by default over one period of the sieve. They are
+//! mapped to
+//!
+//! - frequencies `base * 2^(n * step / 1200)` for a `step` in cents such as
+//!   `step=100c` (the default), or `base + n * step` for a step in Hz. The
+//!   base defaults to the base frequency of the tuning.
+//! - durations of `unit` (default 1 in the duration unit) times the distance
+//!   from each point to the next one.
+
+use crate::duration::{parse_duration, Duration, DurationUnit};
+use crate::error::{Error, Result};
+use crate::sequence::{tokens, Parameter, Token, INLINE_NAME};
+use crate::synth::lcm;
+
+/// Most integers that are tested for a generator, so that sparse sieves
+/// with long periods fail instead of hanging.
+const MAX_SPAN: i64 = 1 << 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sieve {
+    Residue { modulus: u64, residue: u64 },
+    Union(Box<Sieve>, Box<Sieve>),
+    Intersection(Box<Sieve>, Box<Sieve>),
+    Complement(Box<Sieve>),
+}
+
+impl Sieve {
+    pub fn contains(&self, n: i64) -> bool {
+        match self {
+            Sieve::Residue { modulus, residue } => n.rem_euclid(*modulus as i64) as u64 == *residue,
+            Sieve::Union(a, b) => a.contains(n) || b.contains(n),
+            Sieve::Intersection(a, b) => a.contains(n) && b.contains(n),
+            Sieve::Complement(a) => !a.contains(n),
+        }
+    }
+
+    /// The least common multiple of all moduli, after which the sieve
+    /// repeats. Saturates at `u64::MAX`.
+    pub fn period(&self) -> u64 {
+        match self {
+            Sieve::Residue { modulus, .. } => *modulus,
+            Sieve::Union(a, b) | Sieve::Intersection(a, b) => lcm(a.period(), b.period()),
+            Sieve::Complement(a) => a.period(),
+        }
+    }
+}
+
+/// An error message with the byte offset in the expression where it
+/// occurred.
+pub type SieveError = (usize, String);
+
+/// Recursive descent parser over the characters of an expression.
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..]
+            .chars()
+            .find(|c| !c.is_whitespace())
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        let (i, c) = rest.char_indices().find(|(_, c)| !c.is_whitespace())?;
+        self.position += i + c.len_utf8();
+        Some(c)
+    }
+
+    /// An error at the next character.
+    fn error(&self, message: &str) -> SieveError {
+        let rest = self.text[self.position..].trim_start();
+        (self.text.len() - rest.len(), message.to_string())
+    }
+
+    fn number(&mut self) -> std::result::Result<u64, SieveError> {
+        let rest = self.text[self.position..].trim_start();
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let number = rest[..digits]
+            .parse()
+            .map_err(|_| self.error("expected a residue class such as 8@3"))?;
+        self.position = self.text.len() - rest.len() + digits;
+        Ok(number)
+    }
+
+    fn union(&mut self) -> std::result::Result<Sieve, SieveError> {
+        let mut sieve = self.intersection()?;
+        while self.peek() == Some('|') {
+            self.next();
+            sieve = Sieve::Union(Box::new(sieve), Box::new(self.intersection()?));
+        }
+        Ok(sieve)
+    }
+
+    fn intersection(&mut self) -> std::result::Result<Sieve, SieveError> {
+        let mut sieve = self.complement()?;
+        while self.peek() == Some('&') {
+            self.next();
+            sieve = Sieve::Intersection(Box::new(sieve), Box::new(self.complement()?));
+        }
+        Ok(sieve)
+    }
+
+    fn complement(&mut self) -> std::result::Result<Sieve, SieveError> {
+        match self.peek() {
+            Some('~') => {
+                self.next();
+                Ok(Sieve::Complement(Box::new(self.complement()?)))
+            }
+            Some('(') => {
+                self.next();
+                let sieve = self.union()?;
+                if self.peek() != Some(')') {
+                    return Err(self.error("expected `)`"));
+                }
+                self.next();
+                Ok(sieve)
+            }
+            _ => {
+                let modulus_error = self.error("expected a positive modulus");
+                let modulus = self.number()?;
+                if self.peek() != Some('@') {
+                    return Err(self.error("expected a residue class such as 8@3"));
+                }
+                self.next();
+                let residue = self.number()?;
+                if modulus == 0 || modulus > i64::MAX as u64 {
+                    return Err(modulus_error);
+                }
+                Ok(Sieve::Residue {
+                    modulus,
+                    residue: residue % modulus,
+                })
+            }
+        }
+    }
+}
+
+pub fn parse_sieve(expression: &str) -> std::result::Result<Sieve, SieveError> {
+    let mut parser = Parser {
+        text: expression,
+        position: 0,
+    };
+    let sieve = parser.union()?;
+    match parser.peek() {
+        None => Ok(sieve),
+        Some(c) => Err(parser.error(&format!("unexpected `{}`", c))),
+    }
+}
+
+/// Parses the sieve of the `expression` tokens, errors point to the
+/// offending part of a token.
+fn parse_sieve_tokens<'a>(expression: &[Token<'a>]) -> Result<Sieve> {
+    let text: Vec<&str> = expression.iter().map(|t| t.text).collect();
+    let (offset, message) = match parse_sieve(&text.join(" ")) {
+        Ok(sieve) => return Ok(sieve),
+        Err(e) => e,
+    };
+    // The tokens are joined by single spaces, find the one at `offset`.
+    let mut start = 0;
+    let mut at = expression[0];
+    let mut within = 0;
+    for token in expression {
+        if start > offset {
+            break;
+        }
+        at = *token;
+        within = (offset - start).min(token.text.len());
+        start += token.text.len() + 1;
+    }
+    let column = at.column + at.text[..within].chars().count();
+    Err(Token {
+        text: &at.text[within..],
+        column,
+        ..at
+    }
+    .error(&message))
+}
+
+/// A sieve with the range of its points, see the module documentation.
+struct Generator<'a> {
+    sieve: Sieve,
+    /// The first token of the sieve, which errors point to.
+    expression: Token<'a>,
+    from: i64,
+    to: Option<i64>,
+    count: Option<usize>,
+}
+
+impl<'a> Generator<'a> {
+    /// The points of the sieve in `[start, end)`, at most `MAX_SPAN` after
+    /// `from`.
+    fn scan(&self, start: i64, end: i64) -> impl Iterator<Item = i64> + '_ {
+        let end = end.min(self.from.saturating_add(MAX_SPAN));
+        (start..end).filter(move |&n| self.sieve.contains(n))
+    }
+
+    fn too_long(&self) -> Error {
+        self.expression.error(&format!(
+            "the range covers more than {} integers, limit it with to= or count=",
+            MAX_SPAN
+        ))
+    }
+
+    fn points(&self) -> Result<Vec<i64>> {
+        let points: Vec<i64> = match (self.to, self.count) {
+            (_, Some(count)) => {
+                let end = self.to.unwrap_or(i64::MAX);
+                let points: Vec<i64> = self.scan(self.from, end).take(count).collect();
+                if points.len() < count && self.to.is_none() {
+                    return Err(self.expression.error(&format!(
+                        "the sieve has fewer than {} points among {} integers",
+                        count, MAX_SPAN
+                    )));
+                }
+                points
+            }
+            (Some(to), None) if to.saturating_sub(self.from) > MAX_SPAN => {
+                return Err(self.too_long())
+            }
+            (Some(to), None) => self.scan(self.from, to).collect(),
+            (None, None) => {
+                let period = self.sieve.period();
+                if period > MAX_SPAN as u64 {
+                    return Err(self.too_long());
+                }
+                self.scan(self.from, self.from + period as i64).collect()
+            }
+        };
+        if points.is_empty() {
+            return Err(Error::EmptySequence("sieve"));
+        }
+        Ok(points)
+    }
+
+    /// The distances from each point to the next one.
+    fn gaps(&self, points: &[i64]) -> Result<Vec<i64>> {
+        let last = points[points.len() - 1];
+        let next = self
+            .scan(last + 1, i64::MAX)
+            .next()
+            .ok_or_else(|| self.too_long())?;
+        Ok(points
+            .iter()
+            .zip(points[1..].iter().chain(std::iter::once(&next)))
+            .map(|(a, b)| b - a)
+            .collect())
+    }
+}
+
+/// Splits a generator into the sieve, its range and the other parameters.
+fn parse_generator<'a>(tokens: &[Token<'a>]) -> Result<(Generator<'a>, Vec<Parameter<'a>>)> {
+    let (parameters, expression): (Vec<Token>, Vec<Token>) =
+        tokens.iter().partition(|t| t.text.contains('='));
+    let first = match (expression.first(), tokens.first()) {
+        (Some(first), _) => *first,
+        (None, Some(token)) => return Err(token.error("expected a sieve such as 8@0 | 8@3")),
+        (None, None) => return Err(Error::EmptySequence("sieve")),
+    };
+    let sieve = parse_sieve_tokens(&expression)?;
+    let mut generator = Generator {
+        sieve,
+        expression: first,
+        from: 0,
+        to: None,
+        count: None,
+    };
+    let mut rest = vec![];
+    for token in parameters {
+        let parameter = Parameter::parse(token)?;
+        match parameter.name {
+            "from" => {
+                generator.from = parameter.integer()?;
+                // Points are looked for up to `MAX_SPAN` after `from`.
+                if generator.from.checked_add(MAX_SPAN).is_none() {
+                    return Err(parameter.error(&format!(
+                        "expected an integer up to {}",
+                        i64::MAX - MAX_SPAN
+                    )));
+                }
+            }
+            "to" => generator.to = Some(parameter.integer()?),
+            "count" => generator.count = Some(parameter.count(1)?),
+            _ => rest.push(parameter),
+        }
+    }
+    Ok((generator, rest))
+}
+
+/// The interval between neighbouring integers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Cents(f64),
+    Hz(f64),
+}
+
+pub(crate) fn sieve_frequency_tokens(tokens: &[Token], base_frequency: f64) -> Result<Vec<f64>> {
+    let (generator, parameters) = parse_generator(tokens)?;
+    let mut base = base_frequency;
+    let mut step = Step::Cents(100.0);
+    for parameter in parameters {
+        match parameter.name {
+            "base" => {
+                base = parameter
+                    .number()
+                    .ok()
+                    .filter(|&b| b > 0.0)
+                    .ok_or_else(|| parameter.error("expected a base frequency in Hz"))?
+            }
+            "step" => {
+                let number = |n: &str| n.parse::<f64>().ok().filter(|s| s.is_finite());
+                step = match parameter.value.strip_suffix('c') {
+                    Some(cents) => number(cents).map(Step::Cents),
+                    None => number(parameter.value).map(Step::Hz),
+                }
+                .ok_or_else(|| parameter.error("expected a step in Hz or in cents such as 100c"))?
+            }
+            _ => return Err(parameter.unknown()),
+        }
+    }
+    Ok(generator
+        .points()?
+        .into_iter()
+        .map(|n| match step {
+            Step::Cents(cents) => base * 2f64.powf(n as f64 * cents / 1200.0),
+            Step::Hz(hz) => base + n as f64 * hz,
+        })
+        .collect())
+}
+
+pub(crate) fn sieve_duration_tokens(
+    tokens: &[Token],
+    default_unit: DurationUnit,
+) -> Result<Vec<Duration>> {
+    let (generator, parameters) = parse_generator(tokens)?;
+    let mut unit = Duration::new(1.0, default_unit);
+    for parameter in parameters {
+        match parameter.name {
+            "unit" => {
+                unit = parse_duration(parameter.value, default_unit)
+                    .ok()
+                    .filter(|d| d.value().is_finite() && d.value() > 0.0)
+                    .ok_or_else(|| parameter.error("expected a positive duration"))?
+            }
+            _ => return Err(parameter.unknown()),
+        }
+    }
+    let points = generator.points()?;
+    Ok(generator
+        .gaps(&points)?
+        .into_iter()
+        .map(|gap| {
+            let gap = gap as f64;
+            match unit {
+                Duration::Samples(v) => Duration::Samples(v * gap),
+                Duration::Seconds(v) => Duration::Seconds(v * gap),
+            }
+        })
+        .collect())
+}
+
+/// Frequencies of a generator such as `(8@0 | 8@3) & 5@2 base=110 step=100c`.
+pub fn sieve_frequencies(generator: &str, base_frequency: f64) -> Result<Vec<f64>> {
+    let tokens: Vec<Token> = tokens(generator).collect();
+    sieve_frequency_tokens(&tokens, base_frequency).map_err(|e| e.in_file(INLINE_NAME))
+}
+
+/// Durations of a generator such as `(8@0 | 8@3) & 5@2 unit=10ms`.
+pub fn sieve_durations(generator: &str, default_unit: DurationUnit) -> Result<Vec<Duration>> {
+    let tokens: Vec<Token> = tokens(generator).collect();
+    sieve_duration_tokens(&tokens, default_unit).map_err(|e| e.in_file(INLINE_NAME))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The points of `expression` in `[0, end)`.
+    fn points(expression: &str, end: i64) -> Vec<i64> {
+        let sieve = parse_sieve(expression).unwrap();
+        (0..end).filter(|&n| sieve.contains(n)).collect()
+    }
+
+    #[test]
+    fn intersection_binds_tighter_than_union() {
+        assert_eq!(points("(8@0 | 8@3) & 5@2", 40), vec![27, 32]);
+        assert_eq!(points("8@0 | 8@3 & 5@2", 40), vec![0, 8, 16, 24, 27, 32]);
+        assert_eq!(
+            parse_sieve("8@0 | 8@3 & 5@2"),
+            parse_sieve("8@0 | (8@3 & 5@2)")
+        );
+    }
+
+    #[test]
+    fn complement_binds_tightest() {
+        assert_eq!(points("~2@0 & 3@0", 12), vec![3, 9]);
+        assert_eq!(points("~(2@0 & 3@0)", 7), vec![1, 2, 3, 4, 5]);
+        assert_eq!(points("~~4@1", 9), vec![1, 5]);
+    }
+
+    #[test]
+    fn periods_are_the_lcm_of_the_moduli() {
+        assert_eq!(parse_sieve("(8@0 | 8@3) & 5@2").unwrap().period(), 40);
+        assert_eq!(parse_sieve("~6@1 | 4@0").unwrap().period(), 12);
+        assert_eq!(parse_sieve("11@14").unwrap(), parse_sieve("11@3").unwrap());
+    }
+
+    #[test]
+    fn errors_point_into_the_expression() {
+        assert_eq!(parse_sieve("8@0 | | 3@1").unwrap_err().0, 6);
+        assert_eq!(parse_sieve("(8@0 | 3@1").unwrap_err().0, 10);
+        assert_eq!(parse_sieve("0@1").unwrap_err().0, 0);
+        assert_eq!(parse_sieve("8@0 )").unwrap_err().0, 4);
+    }
+
+    #[test]
+    fn ranges_stay_within_i64() {
+        let last = i64::MAX - MAX_SPAN;
+        let error = sieve_frequencies(&format!("3@0 from={}", i64::MAX), 440.0).unwrap_err();
+        assert!(matches!(error, Error::Parse { location, .. } if location.column == 5));
+        let durations = sieve_durations(&format!("3@0 from={}", last), DurationUnit::Samples);
+        assert_eq!(durations.unwrap().len(), 1);
+        let count = format!("7@0 from={} count=3", last);
+        assert_eq!(sieve_frequencies(&count, 1.0).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn generators_take_one_period_by_default() {
+        let frequencies = sieve_frequencies("(8@0 | 8@3) & 5@2 base=100 step=10", 440.0).unwrap();
+        assert_eq!(frequencies, vec![370.0, 420.0]);
+        let durations = sieve_durations("3@0 | 4@1 unit=2ms", DurationUnit::Samples).unwrap();
+        assert_eq!(
+            durations,
+            [1.0, 2.0, 2.0, 1.0, 3.0, 3.0]
+                .iter()
+                .map(|&d| Duration::Seconds(0.002 * d))
+                .collect::<Vec<_>>()
+        );
+    }
+}
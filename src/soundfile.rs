@@ -0,0 +1,536 @@
+use crate::error::{Error, Result};
+use crate::rng::Rng;
+use crate::stream::ScoreStream;
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleFormat {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+    Float64,
+}
+
+impl SampleFormat {
+    pub fn new(float: bool, bits: u16) -> Result<SampleFormat> {
+        match (float, bits) {
+            (false, 16) => Ok(SampleFormat::Int16),
+            (false, 24) => Ok(SampleFormat::Int24),
+            (false, 32) => Ok(SampleFormat::Int32),
+            (true, 32) => Ok(SampleFormat::Float32),
+            (true, 64) => Ok(SampleFormat::Float64),
+            _ => Err(Error::UnsupportedFormat { float, bits }),
+        }
+    }
+
+    pub fn bits(self) -> u16 {
+        match self {
+            SampleFormat::Int16 => 16,
+            SampleFormat::Int24 => 24,
+            SampleFormat::Int32 | SampleFormat::Float32 => 32,
+            SampleFormat::Float64 => 64,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, SampleFormat::Float32 | SampleFormat::Float64)
+    }
+}
+
+/// `Raw` writes headerless little-endian PCM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileType {
+    Wav,
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OutputSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub format: SampleFormat,
+    pub file_type: FileType,
+    /// Apply TPDF dither when writing 16-bit samples.
+    pub dither: bool,
+}
+
+impl OutputSpec {
+    pub fn new(sample_rate: u32) -> OutputSpec {
+        OutputSpec {
+            sample_rate,
+            channels: 1,
+            format: SampleFormat::Int24,
+            file_type: FileType::Wav,
+            dither: true,
+        }
+    }
+
+    /// Size of the WAV header, which more than two channels extend.
+    fn header_size(&self) -> u32 {
+        if self.channels > 2 {
+            HEADER_SIZE + EXTENSIBLE_SIZE
+        } else {
+            HEADER_SIZE
+        }
+    }
+
+    /// Most bytes of audio whose length fits into the 32-bit sizes of the
+    /// header, which count everything but the first 8 bytes of the file.
+    fn max_data_bytes(&self) -> u64 {
+        (u32::MAX - (self.header_size() - 8)) as u64
+    }
+
+    /// The bytes per frame and per second, `None` if they overflow the
+    /// fields of a WAV header.
+    fn block_sizes(&self) -> Option<(u16, u32)> {
+        let block_align = self.channels.checked_mul(self.format.bits() / 8)?;
+        let byte_rate = self.sample_rate.checked_mul(block_align as u32)?;
+        Some((block_align, byte_rate))
+    }
+
+    /// Checks that a WAV header can describe the channels and sample rate.
+    pub fn validate(&self) -> Result<()> {
+        if self.file_type == FileType::Wav && self.block_sizes().is_none() {
+            return Err(Error::UnsupportedLayout {
+                channels: self.channels,
+                sample_rate: self.sample_rate,
+                bits: self.format.bits(),
+            });
+        }
+        Ok(())
+    }
+}
+
+const DITHER_SEED: u64 = 0x05e6_d0d3;
+
+/// Size of a WAV header with a plain `fmt ` chunk, and of the extension of
+/// WAVE_FORMAT_EXTENSIBLE.
+const HEADER_SIZE: u32 = 44;
+const EXTENSIBLE_SIZE: u32 = 24;
+
+/// Name used in error messages when writing to standard output.
+const STDOUT_NAME: &str = "<stdout>";
+
+/// Writes interleaved samples in the range [-1, 1] as a RIFF WAVE stream or
+/// as raw PCM. Integer formats are clipped to that range, float formats are
+/// written unchanged.
+pub struct SoundFileWriter<W: Write> {
+    writer: W,
+    spec: OutputSpec,
+    rng: Rng,
+    data_bytes: u64,
+    /// Most data bytes whose length the header can describe, `None` if the
+    /// length is not written.
+    max_data_bytes: Option<u64>,
+}
+
+impl<W: Write> SoundFileWriter<W> {
+    /// Starts a file whose header is completed by [`finalize`].
+    ///
+    /// [`finalize`]: SoundFileWriter::finalize
+    pub fn new(writer: W, spec: OutputSpec) -> std::io::Result<SoundFileWriter<W>> {
+        SoundFileWriter::with_header_length(writer, spec, 0)
+    }
+
+    /// Starts a stream that cannot be rewound, such as a pipe. The header
+    /// declares the largest possible length, which players and converters
+    /// read as "until the end of the stream", so the stream may exceed it.
+    pub fn new_streaming(writer: W, spec: OutputSpec) -> std::io::Result<SoundFileWriter<W>> {
+        SoundFileWriter::with_header_length(writer, spec, u64::MAX)
+    }
+
+    fn with_header_length(
+        mut writer: W,
+        spec: OutputSpec,
+        data_bytes: u64,
+    ) -> std::io::Result<SoundFileWriter<W>> {
+        if spec.file_type == FileType::Wav {
+            write_header(&mut writer, &spec, data_bytes)?;
+        }
+        let max_data_bytes = Some(spec.max_data_bytes())
+            .filter(|_| spec.file_type == FileType::Wav && data_bytes != u64::MAX);
+        Ok(SoundFileWriter {
+            writer,
+            spec,
+            rng: Rng::new(DITHER_SEED),
+            data_bytes: 0,
+            max_data_bytes,
+        })
+    }
+
+    /// Fails once a WAV file outgrows the 4 GiB that its header can describe.
+    pub fn write_sample(&mut self, sample: f64) -> std::io::Result<()> {
+        let bytes = self.spec.format.bits() as u64 / 8;
+        if self
+            .max_data_bytes
+            .is_some_and(|limit| self.data_bytes + bytes > limit)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the audio exceeds the 4 GiB limit of WAV files, write raw PCM instead",
+            ));
+        }
+        let w = &mut self.writer;
+        match self.spec.format {
+            SampleFormat::Int16 => {
+                let dither = if self.spec.dither {
+                    self.rng.next_f64() - self.rng.next_f64()
+                } else {
+                    0.0
+                };
+                let s = (sample.clamp(-1.0, 1.0) * 32_767.0 + dither).round();
+                w.write_all(&(s.clamp(-32_768.0, 32_767.0) as i16).to_le_bytes())?;
+            }
+            SampleFormat::Int24 => {
+                let s = (sample.clamp(-1.0, 1.0) * 8_388_607.0) as i32;
+                w.write_all(&s.to_le_bytes()[..3])?;
+            }
+            SampleFormat::Int32 => {
+                let s = (sample.clamp(-1.0, 1.0) * 2_147_483_647.0) as i32;
+                w.write_all(&s.to_le_bytes())?;
+            }
+            SampleFormat::Float32 => w.write_all(&(sample as f32).to_le_bytes())?,
+            SampleFormat::Float64 => w.write_all(&sample.to_le_bytes())?,
+        }
+        self.data_bytes += bytes;
+        Ok(())
+    }
+
+    /// Flushes a stream started with [`new_streaming`].
+    ///
+    /// [`new_streaming`]: SoundFileWriter::new_streaming
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> SoundFileWriter<W> {
+    /// Fills in the chunk sizes of the header and flushes the writer.
+    pub fn finalize(mut self) -> std::io::Result<()> {
+        if self.spec.file_type == FileType::Wav {
+            self.writer.seek(SeekFrom::Start(0))?;
+            write_header(&mut self.writer, &self.spec, self.data_bytes)?;
+        }
+        self.writer.flush()
+    }
+}
+
+fn write_header(
+    writer: &mut impl Write,
+    spec: &OutputSpec,
+    data_bytes: u64,
+) -> std::io::Result<()> {
+    let (block_align, byte_rate) = spec.block_sizes().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the channels and sample rate exceed the limits of a WAV header",
+        )
+    })?;
+    let format_tag: u16 = if spec.format.is_float() { 3 } else { 1 };
+    // WAVE_FORMAT_EXTENSIBLE is required for more than two channels. The
+    // channel mask is left empty as the channels are not tied to speaker
+    // positions.
+    let extensible = spec.channels > 2;
+    let header_size = spec.header_size();
+    let fmt_size: u32 = header_size - 28;
+    // Only streams, whose length is unknown, declare the largest length.
+    let data_bytes = data_bytes.min(spec.max_data_bytes()) as u32;
+
+    let mut header = Vec::with_capacity(header_size as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(header_size - 8 + data_bytes).to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&fmt_size.to_le_bytes());
+    let tag: u16 = if extensible { 0xfffe } else { format_tag };
+    header.extend_from_slice(&tag.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.format.bits().to_le_bytes());
+    if extensible {
+        header.extend_from_slice(&22u16.to_le_bytes());
+        header.extend_from_slice(&spec.format.bits().to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        // The sub format GUID: the format tag followed by the fixed suffix
+        // 00000000-0010-8000-00aa00389b71.
+        header.extend_from_slice(&(format_tag as u32).to_le_bytes());
+        header.extend_from_slice(&[
+            0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+        ]);
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_bytes.to_le_bytes());
+    writer.write_all(&header)
+}
+
+/// The file that `output_file` is written to before it is renamed, `None`
+/// if it is written in place. Devices such as `/dev/null` and links are
+/// written in place, as a rename would replace them.
+fn temporary_path(output_file: &str) -> Option<PathBuf> {
+    let path = Path::new(output_file);
+    if fs::symlink_metadata(path).is_ok_and(|m| !m.is_file()) {
+        return None;
+    }
+    let mut name = OsString::from(".");
+    name.push(path.file_name()?);
+    name.push(format!(".{}.tmp", process::id()));
+    Some(path.with_file_name(name))
+}
+
+/// Writes a file with `write`. Regular files are written next to the
+/// target and only replace it once they are complete, so that a failed
+/// render neither leaves an incomplete file behind nor destroys an existing
+/// one.
+fn write_file(
+    spec: &OutputSpec,
+    output_file: &str,
+    write: impl FnOnce(&mut SoundFileWriter<BufWriter<File>>) -> Result<()>,
+) -> Result<()> {
+    let io_error = |e| Error::io(output_file, e);
+    let temporary = temporary_path(output_file);
+    let path = temporary
+        .as_deref()
+        .unwrap_or_else(|| Path::new(output_file));
+    let file = File::create(path).map_err(io_error)?;
+    let result = SoundFileWriter::new(BufWriter::new(file), *spec)
+        .map_err(io_error)
+        .and_then(|mut writer| {
+            write(&mut writer)?;
+            writer.finalize().map_err(io_error)
+        })
+        .and_then(|_| match &temporary {
+            Some(temporary) => fs::rename(temporary, output_file).map_err(io_error),
+            None => Ok(()),
+        });
+    if let (Err(_), Some(temporary)) = (&result, &temporary) {
+        let _ = fs::remove_file(temporary);
+    }
+    result
+}
+
+pub fn write_sf(spec: &OutputSpec, output_file: &str, audio: &[f64]) -> Result<()> {
+    spec.validate()?;
+    write_file(spec, output_file, |writer| {
+        for sample in audio.iter() {
+            writer
+                .write_sample(*sample)
+                .map_err(|e| Error::io(output_file, e))?;
+        }
+        Ok(())
+    })
+}
+
+/// Number of frames rendered and written at a time by [`write_stream`].
+const BLOCK_FRAMES: usize = 4096;
+
+/// Renders `stream` block by block into a file, so that memory use does not
+/// depend on the length of the piece. An `output_file` of `-` writes to
+/// standard output.
+pub fn write_stream(spec: &OutputSpec, output_file: &str, stream: &mut ScoreStream) -> Result<()> {
+    spec.validate()?;
+    if output_file == "-" {
+        let stdout = io::stdout();
+        let io_error = |e| Error::io(STDOUT_NAME, e);
+        let mut writer = SoundFileWriter::new_streaming(BufWriter::new(stdout.lock()), *spec)
+            .map_err(io_error)?;
+        write_blocks(&mut writer, stream, STDOUT_NAME)?;
+        return writer.finish().map_err(io_error);
+    }
+
+    write_file(spec, output_file, |writer| {
+        write_blocks(writer, stream, output_file)
+    })
+}
+
+fn write_blocks<W: Write>(
+    writer: &mut SoundFileWriter<W>,
+    stream: &mut ScoreStream,
+    output_name: &str,
+) -> Result<()> {
+    let mut buffer = vec![0.0; BLOCK_FRAMES * stream.channel_count()];
+    loop {
+        let frames = stream.read(&mut buffer)?;
+        for sample in buffer[..frames * stream.channel_count()].iter() {
+            writer
+                .write_sample(*sample)
+                .map_err(|e| Error::io(output_name, e))?;
+        }
+        if frames < BLOCK_FRAMES {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    const FORMATS: [SampleFormat; 5] = [
+        SampleFormat::Int16,
+        SampleFormat::Int24,
+        SampleFormat::Int32,
+        SampleFormat::Float32,
+        SampleFormat::Float64,
+    ];
+
+    const SAMPLES: [f64; 6] = [0.0, 0.5, -0.25, 1.0, -1.0, 1.5];
+
+    fn write(spec: OutputSpec) -> Vec<u8> {
+        let mut bytes = Cursor::new(vec![]);
+        let mut writer = SoundFileWriter::new(&mut bytes, spec).unwrap();
+        for &sample in SAMPLES.iter() {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    /// Reads a file with hound, scaling integer samples to [-1, 1].
+    fn read(bytes: Vec<u8>) -> (hound::WavSpec, Vec<f64>) {
+        let mut reader = hound::WavReader::new(Cursor::new(bytes)).unwrap();
+        let spec = reader.spec();
+        let samples = match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Int, bits) => {
+                let full_scale = ((1i64 << (bits - 1)) - 1) as f64;
+                reader
+                    .samples::<i32>()
+                    .map(|s| s.unwrap() as f64 / full_scale)
+                    .collect()
+            }
+            (hound::SampleFormat::Float, 32) => {
+                reader.samples::<f32>().map(|s| s.unwrap() as f64).collect()
+            }
+            // hound parses the header of 64-bit floats but cannot decode
+            // their samples, which follow the header.
+            (hound::SampleFormat::Float, _) => {
+                let length = reader.len() as usize;
+                let mut data = vec![];
+                reader.into_inner().read_to_end(&mut data).unwrap();
+                assert_eq!(data.len(), length * 8);
+                data.chunks(8)
+                    .map(|b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                    .collect()
+            }
+        };
+        (spec, samples)
+    }
+
+    #[test]
+    fn wav_files_round_trip_through_hound() {
+        for &format in FORMATS.iter() {
+            // hound only accepts 64-bit floats in extensible headers, which
+            // are written for more than two channels.
+            let channels: &[u16] = match format {
+                SampleFormat::Float64 => &[3],
+                _ => &[1, 2, 3],
+            };
+            for &channels in channels {
+                let spec = OutputSpec {
+                    channels,
+                    format,
+                    dither: false,
+                    ..OutputSpec::new(44_100)
+                };
+                let (wav_spec, samples) = read(write(spec));
+                assert_eq!(wav_spec.channels, channels);
+                assert_eq!(wav_spec.sample_rate, 44_100);
+                assert_eq!(wav_spec.bits_per_sample, format.bits());
+                assert_eq!(
+                    wav_spec.sample_format == hound::SampleFormat::Float,
+                    format.is_float()
+                );
+                assert_eq!(samples.len(), SAMPLES.len());
+                let step = match format {
+                    SampleFormat::Float32 | SampleFormat::Float64 => 0.0,
+                    _ => 1.0 / ((1u64 << (format.bits() - 1)) - 1) as f64,
+                };
+                for (&read, &written) in samples.iter().zip(SAMPLES.iter()) {
+                    let expected = if format.is_float() {
+                        written
+                    } else {
+                        written.clamp(-1.0, 1.0)
+                    };
+                    assert!(
+                        (read - expected).abs() <= step,
+                        "{:?}: read {} for {}",
+                        format,
+                        read,
+                        written
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wav_files_stop_at_the_size_limit() {
+        for &channels in &[1, 3] {
+            let spec = OutputSpec {
+                channels,
+                format: SampleFormat::Int16,
+                ..OutputSpec::new(44_100)
+            };
+            let mut bytes = Cursor::new(vec![]);
+            let mut writer = SoundFileWriter::new(&mut bytes, spec).unwrap();
+            writer.data_bytes = spec.max_data_bytes() - 2;
+            writer.write_sample(0.0).unwrap();
+            assert!(writer.write_sample(0.0).is_err());
+            writer.finalize().unwrap();
+            let header = bytes.into_inner();
+            let size = |at: usize| {
+                u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+            };
+            let data_at = spec.header_size() as usize - 4;
+            assert_eq!(size(4), u32::MAX);
+            assert_eq!(size(data_at) as u64, spec.max_data_bytes());
+        }
+        let mut stream =
+            SoundFileWriter::new_streaming(io::sink(), OutputSpec::new(44_100)).unwrap();
+        stream.data_bytes = u64::from(u32::MAX);
+        assert!(stream.write_sample(0.0).is_ok());
+    }
+
+    #[test]
+    fn failed_renders_keep_existing_files() {
+        let dir = std::env::temp_dir().join(format!("segmod3-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.wav");
+        let output_file = output.to_str().unwrap();
+        fs::write(&output, "previous").unwrap();
+        let spec = OutputSpec::new(44_100);
+        let failed = write_file(&spec, output_file, |writer| {
+            writer.write_sample(0.0).unwrap();
+            Err(Error::EmptySequence("test"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "previous");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        write_sf(&spec, output_file, &[0.0, 0.5]).unwrap();
+        assert_eq!(read(fs::read(&output).unwrap()).1.len(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn oversized_layouts_are_rejected() {
+        let spec = OutputSpec {
+            channels: 9000,
+            format: SampleFormat::Float64,
+            ..OutputSpec::new(44_100)
+        };
+        assert!(spec.validate().is_err());
+        let raw = OutputSpec {
+            file_type: FileType::Raw,
+            ..spec
+        };
+        assert!(raw.validate().is_ok());
+    }
+}
@@ -0,0 +1,454 @@
+use crate::error::{Error, Result};
+use crate::filter::Decimator;
+use crate::noise::Noise;
+use crate::synth::{freq_to_phase_inc, Score, Voice};
+use crate::wave::{fmod, lin_interp, wave, Wave};
+use crate::wavetable::Wavetable;
+
+/// Renders the segments of one voice sample by sample at the rendering rate
+/// of the score, that is the sample rate times the oversampling factor.
+#[derive(Debug, Clone)]
+pub struct SegmentStream {
+    voice: Voice,
+    breakpoints: Vec<f64>,
+    wavetables: Vec<Wavetable>,
+    noise: Noise,
+    sample_rate: u32,
+    oversample: u32,
+    segments: usize,
+    i: usize,
+    cur_wave: Wave,
+    cur_phase: f64,
+    cur_phase_inc: f64,
+    phase_offset: f64,
+    amplitude: f64,
+    previous_amplitude: f64,
+    /// In duration mode, the phase at which the segment starts and the
+    /// breakpoint at which it ends.
+    anchor: f64,
+    target: f64,
+    /// The length of the segment in samples and the time of the current
+    /// sample since its start. In frequency mode the length is the time to
+    /// the next breakpoint.
+    length: f64,
+    position: f64,
+}
+
+impl SegmentStream {
+    /// The voice is expected to be valid, see [`Score::validate`]. Random
+    /// waveforms are seeded with the seed of the score and the `channel`.
+    pub fn new(voice: Voice, score: &Score, channel: usize) -> SegmentStream {
+        let mut stream = SegmentStream {
+            breakpoints: score.breakpoints(),
+            wavetables: score.wavetables.clone(),
+            noise: Noise::new(score.seed, channel),
+            sample_rate: score.sample_rate * score.oversample,
+            oversample: score.oversample,
+            segments: score.length_policy.segment_count(&voice.lengths()),
+            voice,
+            i: 0,
+            cur_wave: Wave::DC(0.0),
+            cur_phase: 0.0,
+            cur_phase_inc: 0.0,
+            phase_offset: 0.0,
+            amplitude: 0.0,
+            previous_amplitude: 0.0,
+            anchor: 0.0,
+            target: 0.0,
+            length: 0.0,
+            position: 0.0,
+        };
+        stream.start_segment();
+        stream
+    }
+
+    /// Index of the segment that is currently rendered.
+    pub fn segment(&self) -> usize {
+        self.i
+    }
+
+    fn start_segment(&mut self) {
+        let i = self.i;
+        let voice = &self.voice;
+        self.cur_phase_inc = match &voice.durations {
+            // In duration mode the phase increment is chosen such that the
+            // region from the last breakpoint to the next one takes the
+            // given duration.
+            Some(durations) => {
+                let duration = durations[i % durations.len()];
+                let (target, region) = breakpoint_after(&self.breakpoints, self.anchor);
+                self.target = target;
+                self.length = whole_samples(duration.in_samples(self.sample_rate, self.oversample));
+                region / self.length
+            }
+            None => {
+                let phase_inc = freq_to_phase_inc(
+                    voice.frequencies[i % voice.frequencies.len()],
+                    self.sample_rate,
+                );
+                self.length = breakpoint_after(&self.breakpoints, self.cur_phase).1 / phase_inc;
+                self.position = 0.0;
+                phase_inc
+            }
+        };
+        self.cur_wave = voice.waves[i % voice.waves.len()];
+        if let Some(widths) = &voice.pulse_widths {
+            self.cur_wave = self.cur_wave.with_pulse_width(widths[i % widths.len()]);
+        }
+        if let Wave::RandomDC = self.cur_wave {
+            self.noise.draw_level();
+        }
+        self.phase_offset = voice.phase_offsets.as_ref().map_or(0.0, |p| p[i % p.len()]);
+        self.previous_amplitude = self.amplitude;
+        self.amplitude = voice.amplitudes.as_ref().map_or(1.0, |a| a[i % a.len()]);
+    }
+}
+
+impl Iterator for SegmentStream {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        if self.i >= self.segments {
+            return None;
+        }
+        let sample = match self.cur_wave {
+            // Clamped for negative frequencies, which run away from the
+            // next breakpoint.
+            Wave::Ramp => lin_interp(
+                (self.position / self.length).clamp(0.0, 1.0),
+                self.previous_amplitude,
+                self.amplitude,
+            ),
+            _ => {
+                self.amplitude
+                    * wave(
+                        self.cur_wave,
+                        self.cur_phase,
+                        self.phase_offset,
+                        self.cur_phase_inc,
+                        &self.wavetables,
+                        &mut self.noise,
+                    )
+            }
+        };
+        if self.voice.durations.is_some() {
+            self.advance_by_time();
+        } else {
+            self.advance_by_phase();
+        }
+        Some(sample)
+    }
+}
+
+impl SegmentStream {
+    /// Moves to the next sample in duration mode. Segments end after their
+    /// length in samples, the fraction of a sample left over is carried into
+    /// the next segment, so that durations add up exactly. The phase is
+    /// computed from the start of the segment rather than accumulated.
+    fn advance_by_time(&mut self) {
+        self.position += 1.0;
+        while self.position >= self.length - SAMPLE_TOLERANCE {
+            self.position = (self.position - self.length).max(0.0);
+            self.i += 1;
+            if self.i >= self.segments {
+                return;
+            }
+            self.anchor = self.target;
+            self.start_segment();
+        }
+        self.cur_phase = fmod(self.anchor + self.position * self.cur_phase_inc, 1.0);
+    }
+
+    /// Moves to the next sample in frequency mode, a segment ends when the
+    /// phase crosses a breakpoint.
+    fn advance_by_phase(&mut self) {
+        self.position += 1.0;
+        let cur_phase = self.cur_phase;
+        let next_phase = cur_phase + self.cur_phase_inc;
+        let crossed = self
+            .breakpoints
+            .iter()
+            .any(|b| (next_phase - b).floor() > (cur_phase - b).floor());
+        self.cur_phase = fmod(next_phase, 1.0);
+
+        if crossed {
+            self.i += 1;
+            self.start_segment();
+        }
+    }
+}
+
+/// The next breakpoint after `phase`, which lies in [0, 1), and the phase
+/// distance to it.
+fn breakpoint_after(breakpoints: &[f64], phase: f64) -> (f64, f64) {
+    match breakpoints.iter().find(|&&b| b > phase) {
+        Some(&b) => (b, b - phase),
+        None => (breakpoints[0], breakpoints[0] + 1.0 - phase),
+    }
+}
+
+/// Fraction of a sample below which times are taken to be equal, so that
+/// rounding errors of durations do not add or drop samples.
+const SAMPLE_TOLERANCE: f64 = 1e-6;
+
+/// Rounds lengths that are a whole number of samples up to rounding errors,
+/// such as `0.1s` at 44.1 kHz, so that they stay exact.
+fn whole_samples(length: f64) -> f64 {
+    let rounded = length.round();
+    if rounded > 0.0 && (length - rounded).abs() < SAMPLE_TOLERANCE {
+        rounded
+    } else {
+        length
+    }
+}
+
+/// A voice at the output sample rate.
+#[derive(Debug, Clone)]
+struct ChannelStream {
+    segments: SegmentStream,
+    decimator: Decimator,
+    finished: bool,
+}
+
+impl Iterator for ChannelStream {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        loop {
+            if let Some(sample) = self.decimator.pop() {
+                return Some(sample);
+            }
+            if self.finished {
+                return None;
+            }
+            match self.segments.next() {
+                Some(sample) => self.decimator.push(sample),
+                None => {
+                    self.finished = true;
+                    self.decimator.finish();
+                }
+            }
+        }
+    }
+}
+
+/// Renders a whole score block by block with constant memory. Channels are
+/// rendered in lockstep and interleaved; channels that end early are padded
+/// with silence until the last one has ended.
+#[derive(Debug, Clone)]
+pub struct ScoreStream {
+    channels: Vec<ChannelStream>,
+    multichannel: bool,
+    frames: usize,
+    max_samples: Option<usize>,
+}
+
+impl ScoreStream {
+    pub fn new(score: &Score) -> Result<ScoreStream> {
+        score.validate()?;
+        Ok(ScoreStream {
+            channels: score
+                .voices()
+                .into_iter()
+                .enumerate()
+                .map(|(channel, voice)| ChannelStream {
+                    segments: SegmentStream::new(voice, score, channel),
+                    decimator: Decimator::new(score.oversample as usize),
+                    finished: false,
+                })
+                .collect(),
+            multichannel: !score.channels.is_empty(),
+            frames: 0,
+            max_samples: score
+                .max_duration
+                .map(|limit| limit.in_samples(score.sample_rate, 1) as usize),
+        })
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Fills `buffer` with interleaved frames and returns the number of
+    /// frames written, which is only smaller than the capacity of the
+    /// buffer at the end of the score. The length of the buffer must be a
+    /// multiple of the channel count.
+    pub fn read(&mut self, buffer: &mut [f64]) -> Result<usize> {
+        let mut frames = 0;
+        for frame in buffer.chunks_exact_mut(self.channels.len()) {
+            if !self.read_frame(frame)? {
+                break;
+            }
+            frames += 1;
+        }
+        Ok(frames)
+    }
+
+    /// Renders a single frame, returns `false` once all channels have ended.
+    pub fn read_frame(&mut self, frame: &mut [f64]) -> Result<bool> {
+        let mut running = false;
+        for (sample, channel) in frame.iter_mut().zip(self.channels.iter_mut()) {
+            match channel.next() {
+                Some(s) => {
+                    *sample = s;
+                    running = true;
+                }
+                None => *sample = 0.0,
+            }
+        }
+        if !running {
+            return Ok(false);
+        }
+        if self.max_samples.is_some_and(|limit| self.frames >= limit) {
+            let (channel, stream) = self
+                .channels
+                .iter()
+                .enumerate()
+                .find(|(_, c)| !c.finished)
+                .unwrap_or((0, &self.channels[0]));
+            return Err(Error::SampleLimit {
+                limit: self.frames,
+                segment: stream.segments.segment(),
+            }
+            .in_channel(channel, self.multichannel));
+        }
+        self.frames += 1;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duration::Duration;
+    use crate::markov::parse_chain;
+    use crate::synth::LengthPolicy;
+
+    fn duration_score(durations: &[Duration]) -> Score {
+        let mut score = Score::new(vec![], vec![Wave::Sine]);
+        score.durations = Some(durations.to_vec());
+        score
+    }
+
+    /// The number of samples of each segment at the rendering rate.
+    fn segment_lengths(score: &Score) -> Vec<usize> {
+        let voice = score.voices().remove(0);
+        let mut stream = SegmentStream::new(voice, score, 0);
+        let mut lengths = vec![];
+        loop {
+            let segment = stream.segment();
+            if stream.next().is_none() {
+                return lengths;
+            }
+            if lengths.len() <= segment {
+                lengths.resize(segment + 1, 0);
+            }
+            lengths[segment] += 1;
+        }
+    }
+
+    fn frames(score: &Score) -> usize {
+        let mut stream = ScoreStream::new(score).unwrap();
+        let mut frame = vec![0.0; stream.channel_count()];
+        let mut frames = 0;
+        while stream.read_frame(&mut frame).unwrap() {
+            frames += 1;
+        }
+        frames
+    }
+
+    #[test]
+    fn sample_durations_are_exact() {
+        let score = duration_score(&[Duration::Samples(480.0)]);
+        assert_eq!(segment_lengths(&score), vec![480]);
+        assert_eq!(frames(&score), 480);
+        let score = duration_score(&[Duration::Samples(3.0), Duration::Samples(7.0)]);
+        assert_eq!(segment_lengths(&score), vec![3, 7]);
+        assert_eq!(frames(&score), 10);
+    }
+
+    #[test]
+    fn durations_in_seconds_are_exact() {
+        let score = duration_score(&[Duration::Seconds(0.01)]);
+        assert_eq!(frames(&score), 480);
+        let mut score = duration_score(&[Duration::Seconds(0.1)]);
+        score.sample_rate = 44_100;
+        assert_eq!(frames(&score), 4410);
+    }
+
+    #[test]
+    fn fractions_of_samples_carry_over() {
+        let score = duration_score(&[Duration::Samples(2.5); 3]);
+        assert_eq!(segment_lengths(&score), vec![3, 2, 3]);
+        let mut score = duration_score(&[
+            Duration::Seconds(0.001),
+            Duration::Seconds(0.002),
+            Duration::Seconds(0.007),
+        ]);
+        score.sample_rate = 44_100;
+        assert_eq!(segment_lengths(&score), vec![45, 88, 308]);
+        assert_eq!(frames(&score), 441);
+        let mut score = duration_score(&[Duration::Samples(0.3)]);
+        score.length_policy = LengthPolicy::Segments(10);
+        assert_eq!(frames(&score), 3);
+    }
+
+    #[test]
+    fn durations_take_precedence_over_generated_frequencies() {
+        let mut score = duration_score(&[Duration::Samples(1000.0)]);
+        score.frequency_chain =
+            Some(parse_chain("440 220 330", 1, 4, &crate::sequence::parse_float).unwrap());
+        assert_eq!(frames(&score), 1000);
+    }
+
+    #[test]
+    fn oversampled_durations_are_exact() {
+        let mut score = duration_score(&[Duration::Seconds(0.01), Duration::Samples(7.0)]);
+        score.oversample = 4;
+        assert_eq!(segment_lengths(&score), vec![1920, 28]);
+        assert_eq!(frames(&score), 487);
+    }
+
+    #[test]
+    fn explicit_breakpoints_keep_durations_exact() {
+        let mut score = duration_score(&[Duration::Samples(480.0), Duration::Seconds(0.001)]);
+        score.breakpoint_phases = Some(vec![0.1, 0.35, 0.8]);
+        score.length_policy = LengthPolicy::Segments(5);
+        assert_eq!(segment_lengths(&score), vec![480, 48, 480, 48, 480]);
+        assert_eq!(frames(&score), 1536);
+    }
+
+    #[test]
+    fn segments_end_at_breakpoint_crossings() {
+        // 375 Hz at 48 kHz advances the phase by exactly 1/128 per sample.
+        let mut score = Score::new(vec![375.0], vec![Wave::Sine]);
+        assert_eq!(segment_lengths(&score), vec![128]);
+        score.breakpoints_per_cycle = 4;
+        score.length_policy = LengthPolicy::Segments(6);
+        assert_eq!(segment_lengths(&score), vec![32; 6]);
+        // Phases carry over the crossing, so segments between breakpoints
+        // that fall between samples differ by one sample.
+        score.breakpoints_per_cycle = 3;
+        assert_eq!(segment_lengths(&score), vec![43, 43, 42, 43, 43, 42]);
+    }
+
+    #[test]
+    fn segments_take_their_frequency_to_the_next_breakpoint() {
+        let mut score = Score::new(vec![375.0, 750.0], vec![Wave::Sine]);
+        score.breakpoints_per_cycle = 2;
+        assert_eq!(segment_lengths(&score), vec![64, 32]);
+        score.breakpoint_phases = Some(vec![0.75, 0.25]);
+        score.length_policy = LengthPolicy::Segments(4);
+        assert_eq!(segment_lengths(&score), vec![32, 32, 64, 32]);
+    }
+
+    #[test]
+    fn ramps_join_the_amplitudes_of_segments() {
+        let mut score = duration_score(&[Duration::Samples(4.0)]);
+        score.waves = vec![Wave::Ramp];
+        score.amplitudes = Some(vec![1.0, -1.0]);
+        let voice = score.voices().remove(0);
+        let samples: Vec<f64> = SegmentStream::new(voice, &score, 0).collect();
+        assert_eq!(samples, vec![0.0, 0.25, 0.5, 0.75, 1.0, 0.5, 0.0, -0.5]);
+    }
+}
@@ -0,0 +1,451 @@
+use crate::duration::Duration;
+use crate::error::{Error, Result};
+use crate::gendyn::Gendyn;
+use crate::markov::{MarkovChain, FREQUENCY_STREAM, WAVE_STREAM};
+use crate::rng::Rng;
+use crate::stream::ScoreStream;
+use crate::wave::{Curve, Wave, MAX_CURVE_AMOUNT};
+use crate::wavetable::Wavetable;
+use std::cmp::max;
+use std::convert::TryFrom;
+
+/// A complete description of a piece: the segment sequences and the
+/// parameters needed to render them.
+///
+/// The sequences of the score are shared by all channels. Without any
+/// `channels` the score renders a single mono stream.
+#[derive(Debug, Clone)]
+pub struct Score {
+    pub sample_rate: u32,
+    /// Number of equally spaced breakpoints per cycle, each of them starts a
+    /// new segment. Ignored if `breakpoint_phases` is given.
+    pub breakpoints_per_cycle: u16,
+    /// Explicit breakpoint phases in [0, 1).
+    pub breakpoint_phases: Option<Vec<f64>>,
+    pub frequencies: Vec<f64>,
+    /// Segment durations, which replace the frequencies if given.
+    pub durations: Option<Vec<Duration>>,
+    /// Generates the durations and amplitudes, replacing the shared
+    /// frequencies, durations and amplitudes.
+    pub gendyn: Option<Gendyn>,
+    /// Generates the shared frequencies.
+    pub frequency_chain: Option<MarkovChain<f64>>,
+    /// Generates the shared waveforms.
+    pub wave_chain: Option<MarkovChain<Wave>>,
+    pub waves: Vec<Wave>,
+    /// The tables that `Wave::Table` segments refer to.
+    pub wavetables: Vec<Wavetable>,
+    pub phase_offsets: Option<Vec<f64>>,
+    /// Gain applied to each segment.
+    pub amplitudes: Option<Vec<f64>>,
+    /// Width of each pulse segment, replaces the widths of the waveforms.
+    pub pulse_widths: Option<Vec<f64>>,
+    pub channels: Vec<Channel>,
+    pub length_policy: LengthPolicy,
+    /// Render pulse and saw segments with their band-limited versions.
+    pub bandlimit: bool,
+    /// Render at this multiple of the sample rate, then low-pass filter and
+    /// decimate.
+    pub oversample: u32,
+    /// Rendering fails once this much audio has been produced per channel,
+    /// `None` renders without limit.
+    pub max_duration: Option<Duration>,
+    /// Seed of the random waveforms, equal seeds give identical renders.
+    pub seed: u64,
+}
+
+/// The render limit of new scores, which stops sequences that would never
+/// end, e.g. of near zero frequencies.
+pub const DEFAULT_MAX_DURATION: Duration = Duration::Seconds(4.0 * 3600.0);
+
+/// How many segments are rendered when the sequences differ in length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LengthPolicy {
+    /// Until the longest sequence has been played once.
+    Longest,
+    /// Until the shortest sequence has been played once.
+    Shortest,
+    /// Until all sequences realign, the least common multiple of the lengths.
+    Lcm,
+    /// An explicit number of segments.
+    Segments(usize),
+    /// A multiple of the length of the longest sequence.
+    Repeat(usize),
+}
+
+impl LengthPolicy {
+    /// Parses `longest`, `shortest`, `lcm`, a segment count such as `100` or
+    /// a repeat factor such as `3x`. Counts and factors must be positive.
+    pub fn parse(policy: &str) -> Option<LengthPolicy> {
+        let positive = |n: &str| n.parse::<usize>().ok().filter(|&n| n > 0);
+        match policy.to_lowercase().as_str() {
+            "longest" => Some(LengthPolicy::Longest),
+            "shortest" => Some(LengthPolicy::Shortest),
+            "lcm" => Some(LengthPolicy::Lcm),
+            p => match p.strip_suffix('x') {
+                Some(factor) => positive(factor).map(LengthPolicy::Repeat),
+                None => positive(p).map(LengthPolicy::Segments),
+            },
+        }
+    }
+
+    /// The number of segments for sequences of the given lengths.
+    pub fn segment_count(self, lengths: &[usize]) -> usize {
+        let longest = lengths.iter().copied().max().unwrap_or(0);
+        match self {
+            LengthPolicy::Longest => longest,
+            LengthPolicy::Shortest => lengths.iter().copied().min().unwrap_or(0),
+            LengthPolicy::Lcm => {
+                let lcm = lengths.iter().fold(1, |a, &b| lcm(a, b as u64));
+                usize::try_from(lcm).unwrap_or(usize::MAX)
+            }
+            LengthPolicy::Segments(n) => n,
+            LengthPolicy::Repeat(factor) => longest.saturating_mul(factor),
+        }
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Least common multiple, 0 if either is 0. Saturates at `u64::MAX` instead
+/// of overflowing.
+pub(crate) fn lcm(a: u64, b: u64) -> u64 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        (a / gcd(a, b)).saturating_mul(b)
+    }
+}
+
+/// Per-channel settings. Sequences that are given replace the shared ones of
+/// the score, the rotation is applied to all sequences of the channel.
+#[derive(Debug, Clone, Default)]
+pub struct Channel {
+    pub frequencies: Option<Vec<f64>>,
+    pub durations: Option<Vec<Duration>>,
+    pub waves: Option<Vec<Wave>>,
+    pub phase_offsets: Option<Vec<f64>>,
+    pub amplitudes: Option<Vec<f64>>,
+    pub pulse_widths: Option<Vec<f64>>,
+    /// Number of entries by which each sequence is rotated to the left.
+    pub rotation: usize,
+}
+
+/// The sequences of one channel, resolved from a score.
+#[derive(Debug, Clone)]
+pub struct Voice {
+    pub frequencies: Vec<f64>,
+    pub durations: Option<Vec<Duration>>,
+    pub waves: Vec<Wave>,
+    pub phase_offsets: Option<Vec<f64>>,
+    pub amplitudes: Option<Vec<f64>>,
+    pub pulse_widths: Option<Vec<f64>>,
+}
+
+fn rotated<T: Clone>(values: &[T], n: usize) -> Vec<T> {
+    let mut values = values.to_vec();
+    if !values.is_empty() {
+        let len = values.len();
+        values.rotate_left(n % len);
+    }
+    values
+}
+
+impl Voice {
+    pub fn rotated(&self, n: usize) -> Voice {
+        Voice {
+            frequencies: rotated(&self.frequencies, n),
+            durations: self.durations.as_ref().map(|d| rotated(d, n)),
+            waves: rotated(&self.waves, n),
+            phase_offsets: self.phase_offsets.as_ref().map(|p| rotated(p, n)),
+            amplitudes: self.amplitudes.as_ref().map(|a| rotated(a, n)),
+            pulse_widths: self.pulse_widths.as_ref().map(|w| rotated(w, n)),
+        }
+    }
+
+    /// Lengths of all sequences of the voice.
+    pub fn lengths(&self) -> Vec<usize> {
+        let timing = self
+            .durations
+            .as_ref()
+            .map_or(self.frequencies.len(), Vec::len);
+        let mut lengths = vec![timing, self.waves.len()];
+        lengths.extend(self.phase_offsets.as_ref().map(Vec::len));
+        lengths.extend(self.amplitudes.as_ref().map(Vec::len));
+        lengths.extend(self.pulse_widths.as_ref().map(Vec::len));
+        lengths
+    }
+
+    fn validate(&self) -> Result<()> {
+        match &self.durations {
+            Some(durations) => {
+                let values: Vec<f64> = durations.iter().map(|d| d.value()).collect();
+                check_values("duration", &values, "a positive duration", |d| d > 0.0)?;
+            }
+            None => check_values("frequency", &self.frequencies, "a positive number", |f| {
+                f > 0.0
+            })?,
+        }
+        if self.waves.is_empty() {
+            return Err(Error::EmptySequence("waveform"));
+        }
+        for (index, w) in self.waves.iter().enumerate() {
+            let (value, expected) = match *w {
+                Wave::DC(dc) if !dc.is_finite() => (dc, "a finite DC value"),
+                Wave::Pulse(width) | Wave::BlPulse(width) if !(0.0..=1.0).contains(&width) => {
+                    (width, "a pulse width in [0, 1]")
+                }
+                Wave::Triangle(skew, _) if !(0.0..=1.0).contains(&skew) => {
+                    (skew, "a triangle skew in [0, 1]")
+                }
+                w => match w.curve().map(Curve::amount) {
+                    Some(k) if !(-MAX_CURVE_AMOUNT..=MAX_CURVE_AMOUNT).contains(&k) => {
+                        (k, "a curve amount in [-50, 50]")
+                    }
+                    _ => continue,
+                },
+            };
+            return Err(Error::InvalidValue {
+                sequence: "waveform",
+                index,
+                value,
+                expected,
+            });
+        }
+        if let Some(phase_offsets) = &self.phase_offsets {
+            check_values("phase offset", phase_offsets, "a finite number", |_| true)?;
+        }
+        if let Some(amplitudes) = &self.amplitudes {
+            check_values("amplitude", amplitudes, "a finite number", |_| true)?;
+        }
+        if let Some(pulse_widths) = &self.pulse_widths {
+            check_values("pulse width", pulse_widths, "a width in [0, 1]", |w| {
+                (0.0..=1.0).contains(&w)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Score {
+    pub fn new(frequencies: Vec<f64>, waves: Vec<Wave>) -> Score {
+        Score {
+            sample_rate: 48000,
+            breakpoints_per_cycle: 1,
+            breakpoint_phases: None,
+            frequencies,
+            durations: None,
+            gendyn: None,
+            frequency_chain: None,
+            wave_chain: None,
+            waves,
+            wavetables: vec![],
+            phase_offsets: None,
+            amplitudes: None,
+            pulse_widths: None,
+            channels: vec![],
+            length_policy: LengthPolicy::Longest,
+            bandlimit: false,
+            oversample: 1,
+            max_duration: Some(DEFAULT_MAX_DURATION),
+            seed: 0,
+        }
+    }
+
+    /// The sorted breakpoint phases of a cycle.
+    pub fn breakpoints(&self) -> Vec<f64> {
+        match &self.breakpoint_phases {
+            Some(phases) => {
+                let mut phases = phases.clone();
+                phases.sort_by(f64::total_cmp);
+                phases.dedup();
+                phases
+            }
+            None => {
+                let n = self.breakpoints_per_cycle;
+                (0..n).map(|k| k as f64 / n as f64).collect()
+            }
+        }
+    }
+
+    pub fn channel_count(&self) -> usize {
+        max(1, self.channels.len())
+    }
+
+    /// Resolves the sequences of every channel.
+    pub fn voices(&self) -> Vec<Voice> {
+        let mut voices = self.channel_voices();
+        for (voice, channel) in voices.iter_mut().zip(&self.channels) {
+            *voice = voice.rotated(channel.rotation);
+        }
+        if self.bandlimit {
+            for voice in voices.iter_mut() {
+                for w in voice.waves.iter_mut() {
+                    *w = w.bandlimited();
+                }
+            }
+        }
+        voices
+    }
+
+    /// The sequences of every channel before their rotation, in the order of
+    /// the score so that errors point to the entries as written.
+    fn channel_voices(&self) -> Vec<Voice> {
+        let mut shared = Voice {
+            frequencies: self.frequencies.clone(),
+            durations: self.durations.clone(),
+            waves: self.waves.clone(),
+            phase_offsets: self.phase_offsets.clone(),
+            amplitudes: self.amplitudes.clone(),
+            pulse_widths: self.pulse_widths.clone(),
+        };
+        if let Some(chain) = &self.frequency_chain {
+            shared.frequencies = chain.generate(&mut Rng::new(self.seed ^ FREQUENCY_STREAM));
+        }
+        if let Some(chain) = &self.wave_chain {
+            shared.waves = chain.generate(&mut Rng::new(self.seed ^ WAVE_STREAM));
+        }
+        if let Some(gendyn) = &self.gendyn {
+            let (durations, amplitudes) = gendyn.generate(self.sample_rate, self.seed);
+            shared.frequencies = vec![];
+            shared.durations = Some(durations);
+            shared.amplitudes = Some(amplitudes);
+        }
+        if self.channels.is_empty() {
+            return vec![shared];
+        }
+        self.channels
+            .iter()
+            .map(|channel| {
+                // A channel's own frequencies or durations replace both
+                // shared timing sequences.
+                let (frequencies, durations) = match (&channel.frequencies, &channel.durations) {
+                    (None, None) => (shared.frequencies.clone(), shared.durations.clone()),
+                    (f, d) => (f.clone().unwrap_or_default(), d.clone()),
+                };
+                Voice {
+                    frequencies,
+                    durations,
+                    waves: channel
+                        .waves
+                        .clone()
+                        .unwrap_or_else(|| shared.waves.clone()),
+                    phase_offsets: channel
+                        .phase_offsets
+                        .clone()
+                        .or_else(|| shared.phase_offsets.clone()),
+                    amplitudes: channel
+                        .amplitudes
+                        .clone()
+                        .or_else(|| shared.amplitudes.clone()),
+                    pulse_widths: channel
+                        .pulse_widths
+                        .clone()
+                        .or_else(|| shared.pulse_widths.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Checks that the score can be rendered: all sequences are non-empty
+    /// and every value is finite, frequencies are also strictly positive.
+    pub fn validate(&self) -> Result<()> {
+        if self.sample_rate == 0 {
+            return Err(Error::InvalidSampleRate);
+        }
+        match &self.breakpoint_phases {
+            Some(phases) => check_values("breakpoint phase", phases, "a phase in [0, 1)", |ph| {
+                (0.0..1.0).contains(&ph)
+            })?,
+            None if self.breakpoints_per_cycle == 0 => {
+                return Err(Error::EmptySequence("breakpoint"))
+            }
+            None => (),
+        }
+        if self.oversample == 0 || self.sample_rate.checked_mul(self.oversample).is_none() {
+            return Err(Error::InvalidOversample(self.oversample));
+        }
+        if let Some(gendyn) = &self.gendyn {
+            gendyn.validate(self.sample_rate)?;
+        }
+        if let Some(limit) = self.max_duration {
+            if !(limit.value().is_finite() && limit.value() >= 0.0) {
+                return Err(Error::InvalidMaxDuration(limit.value()));
+            }
+        }
+        for (channel, voice) in self.channel_voices().iter().enumerate() {
+            voice
+                .validate()
+                .and_then(|_| self.check_wavetables(voice))
+                .map_err(|e| e.in_channel(channel, !self.channels.is_empty()))?;
+        }
+        Ok(())
+    }
+
+    fn check_wavetables(&self, voice: &Voice) -> Result<()> {
+        for (index, w) in voice.waves.iter().enumerate() {
+            if let Wave::Table(table) = *w {
+                if table >= self.wavetables.len() {
+                    return Err(Error::InvalidValue {
+                        sequence: "waveform",
+                        index,
+                        value: table as f64,
+                        expected: "the index of a wavetable",
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders all channels, interleaved frame by frame.
+    pub fn render(&self) -> Result<Vec<f64>> {
+        synthesize(self)
+    }
+}
+
+fn check_values(
+    sequence: &'static str,
+    values: &[f64],
+    expected: &'static str,
+    valid: impl Fn(f64) -> bool,
+) -> Result<()> {
+    if values.is_empty() {
+        return Err(Error::EmptySequence(sequence));
+    }
+    match values.iter().position(|&v| !v.is_finite() || !valid(v)) {
+        Some(index) => Err(Error::InvalidValue {
+            sequence,
+            index,
+            value: values[index],
+            expected,
+        }),
+        None => Ok(()),
+    }
+}
+
+pub fn freq_to_sample_length(freq: f64, sample_rate: u32) -> f64 {
+    sample_rate as f64 / freq
+}
+
+pub fn freq_to_phase_inc(freq: f64, sample_rate: u32) -> f64 {
+    freq / sample_rate as f64
+}
+
+/// Renders the whole score into memory, interleaved frame by frame. Use a
+/// [`ScoreStream`] for long pieces.
+pub fn synthesize(score: &Score) -> Result<Vec<f64>> {
+    let mut stream = ScoreStream::new(score)?;
+    let mut frame = vec![0.0; stream.channel_count()];
+    let mut output = vec![];
+    while stream.read_frame(&mut frame)? {
+        output.extend_from_slice(&frame);
+    }
+    Ok(output)
+}
@@ -0,0 +1,310 @@
+use crate::noise::Noise;
+use crate::wavetable::{find_wavetable, Wavetable};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Wave {
+    Sine,
+    Cosine,
+    /// Rectangle wave that is high for the given fraction of the cycle.
+    Pulse(f64),
+    /// Triangle wave whose rising ramp takes the given fraction of the
+    /// cycle, 0.5 is symmetric, 0 and 1 are saws.
+    Triangle(f64, Curve),
+    SawUp(Curve),
+    SawDown(Curve),
+    DC(f64),
+    /// Band-limited (PolyBLEP) versions of the discontinuous waveforms.
+    BlPulse(f64),
+    BlSawUp(Curve),
+    BlSawDown(Curve),
+    /// Index of a wavetable of the score.
+    Table(usize),
+    WhiteNoise,
+    PinkNoise,
+    /// A random level in [-1, 1) that is drawn anew for every segment.
+    RandomDC,
+    /// A straight line from the amplitude of the previous segment to the
+    /// amplitude of this one, rendered by [`crate::stream::SegmentStream`].
+    Ramp,
+}
+
+/// Shape of the ramps of triangle and saw waves.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// Starts slowly and speeds up, more so for larger amounts.
+    Exp(f64),
+    /// Starts quickly and slows down, the mirror image of `Exp`.
+    Log(f64),
+}
+
+/// Largest magnitude of curve amounts, the exponentials of larger ones
+/// overflow.
+pub const MAX_CURVE_AMOUNT: f64 = 50.0;
+
+impl Curve {
+    /// Maps the position on a ramp, `x` in [0, 1], to the curved position.
+    pub fn apply(self, x: f64) -> f64 {
+        let y = match self {
+            Curve::Linear => x,
+            Curve::Exp(k) if k != 0.0 => (k * x).exp_m1() / k.exp_m1(),
+            // `e^k - 1` rounds to -1 for large negative amounts, use the
+            // point symmetric curve of the positive amount instead.
+            Curve::Log(k) if k < 0.0 => 1.0 - Curve::Log(-k).apply(1.0 - x),
+            Curve::Log(k) if k > 0.0 => (x * k.exp_m1()).ln_1p() / k,
+            _ => x,
+        };
+        y.clamp(0.0, 1.0)
+    }
+
+    /// The amount of curves, 0 for linear ones.
+    pub fn amount(self) -> f64 {
+        match self {
+            Curve::Linear => 0.0,
+            Curve::Exp(k) | Curve::Log(k) => k,
+        }
+    }
+}
+
+impl Wave {
+    /// Replaces a discontinuous waveform by its band-limited version.
+    pub fn bandlimited(self) -> Wave {
+        match self {
+            Wave::Pulse(width) => Wave::BlPulse(width),
+            Wave::SawUp(curve) => Wave::BlSawUp(curve),
+            Wave::SawDown(curve) => Wave::BlSawDown(curve),
+            w => w,
+        }
+    }
+
+    /// Sets the width of pulse waves, other waveforms are returned as is.
+    pub fn with_pulse_width(self, width: f64) -> Wave {
+        match self {
+            Wave::Pulse(_) => Wave::Pulse(width),
+            Wave::BlPulse(_) => Wave::BlPulse(width),
+            w => w,
+        }
+    }
+
+    /// The curve of ramps, `None` for waveforms without ramps.
+    pub fn curve(self) -> Option<Curve> {
+        match self {
+            Wave::Triangle(_, curve)
+            | Wave::SawUp(curve)
+            | Wave::SawDown(curve)
+            | Wave::BlSawUp(curve)
+            | Wave::BlSawDown(curve) => Some(curve),
+            _ => None,
+        }
+    }
+
+    /// Sets the curve of ramps, `None` for waveforms without ramps.
+    pub fn with_curve(self, curve: Curve) -> Option<Wave> {
+        match self {
+            Wave::Triangle(skew, _) => Some(Wave::Triangle(skew, curve)),
+            Wave::SawUp(_) => Some(Wave::SawUp(curve)),
+            Wave::SawDown(_) => Some(Wave::SawDown(curve)),
+            Wave::BlSawUp(_) => Some(Wave::BlSawUp(curve)),
+            Wave::BlSawDown(_) => Some(Wave::BlSawDown(curve)),
+            _ => None,
+        }
+    }
+}
+
+pub const DEFAULT_PULSE_WIDTH: f64 = 0.5;
+pub const DEFAULT_TRIANGLE_SKEW: f64 = 0.5;
+
+/// Parses a parameter in [0, 1] such as the width of `p0.2`, an empty
+/// parameter is the default one.
+fn parse_fraction(text: &str, default: f64, expected: &str) -> Result<f64, String> {
+    if text.is_empty() {
+        return Ok(default);
+    }
+    text.parse::<f64>()
+        .ok()
+        .filter(|w| (0.0..=1.0).contains(w))
+        .ok_or_else(|| format!("expected {} in [0, 1]", expected))
+}
+
+fn parse_pulse_width(width: &str) -> Result<f64, String> {
+    parse_fraction(width, DEFAULT_PULSE_WIDTH, "a pulse width")
+}
+
+/// Parses `lin`, `exp2` or `log3`, amounts are limited to
+/// `MAX_CURVE_AMOUNT`.
+fn parse_curve(curve: &str) -> Result<Curve, String> {
+    let amount = |k: &str| {
+        k.parse::<f64>()
+            .ok()
+            .filter(|k| k.abs() <= MAX_CURVE_AMOUNT)
+    };
+    let parsed = if curve == "lin" {
+        Some(Curve::Linear)
+    } else if let Some(k) = curve.strip_prefix("exp") {
+        amount(k).map(Curve::Exp)
+    } else if let Some(k) = curve.strip_prefix("log") {
+        amount(k).map(Curve::Log)
+    } else {
+        None
+    };
+    parsed.ok_or_else(|| {
+        format!(
+            "expected a curve such as lin, exp2 or log3 with an amount in [-{0}, {0}]",
+            MAX_CURVE_AMOUNT
+        )
+    })
+}
+
+/// Parses a waveform such as `s`, `p0.2`, `t0.3` or `u:exp2`. Triangles and
+/// saws take a ramp curve after a colon: `lin`, `exp<amount>` or
+/// `log<amount>`. `@name` refers to one of the `tables`.
+pub fn parse_wave(wave: &str, tables: &[Wavetable]) -> Result<Wave, String> {
+    if let Some(name) = wave.strip_prefix('@') {
+        return find_wavetable(tables, name)
+            .map(Wave::Table)
+            .ok_or_else(|| String::from("unknown wavetable"));
+    }
+    let lc_wave = wave.to_lowercase();
+    let (shape, curve) = match lc_wave.split_once(':') {
+        Some((shape, curve)) => (shape, Some(parse_curve(curve)?)),
+        None => (lc_wave.as_str(), None),
+    };
+    let wave = match shape {
+        "s" => Wave::Sine,
+        "c" => Wave::Cosine,
+        "u" => Wave::SawUp(Curve::Linear),
+        "d" => Wave::SawDown(Curve::Linear),
+        "bu" => Wave::BlSawUp(Curve::Linear),
+        "bd" => Wave::BlSawDown(Curve::Linear),
+        "n" => Wave::WhiteNoise,
+        "pn" => Wave::PinkNoise,
+        "r" => Wave::RandomDC,
+        "l" => Wave::Ramp,
+        w if w.starts_with("bp") => Wave::BlPulse(parse_pulse_width(&w[2..])?),
+        w if w.starts_with('p') => Wave::Pulse(parse_pulse_width(&w[1..])?),
+        w if w.starts_with('t') => Wave::Triangle(
+            parse_fraction(&w[1..], DEFAULT_TRIANGLE_SKEW, "a triangle skew")?,
+            Curve::Linear,
+        ),
+        _ => shape.parse::<f64>().map(Wave::DC).map_err(|_| {
+            String::from(
+                "expected a waveform (s, c, p, p0.2, t, t0.3, u, d, bp, bu, bd, n, pn, r, l, \
+                 @table) or a DC value",
+            )
+        })?,
+    };
+    match curve {
+        Some(curve) => wave
+            .with_curve(curve)
+            .ok_or_else(|| String::from("only t, u, d, bu and bd take a curve")),
+        None => Ok(wave),
+    }
+}
+
+/// `phase_inc` is only used by the band-limited waveforms, `tables` only by
+/// wavetables and `noise` only by the random waveforms. Ramps depend on the
+/// neighbouring segments, on their own they are a constant 1, which the
+/// amplitude of the segment scales.
+pub fn wave(
+    wave: Wave,
+    cur_phase: f64,
+    phase_offset: f64,
+    phase_inc: f64,
+    tables: &[Wavetable],
+    noise: &mut Noise,
+) -> f64 {
+    match wave {
+        Wave::Sine => sine(cur_phase, phase_offset),
+        Wave::Cosine => cosine(cur_phase, phase_offset),
+        Wave::Pulse(width) => pulse(cur_phase, phase_offset, width),
+        Wave::Triangle(skew, curve) => triangle(cur_phase, phase_offset, skew, curve),
+        Wave::SawUp(curve) => saw_up(cur_phase, phase_offset, curve),
+        Wave::SawDown(curve) => saw_down(cur_phase, phase_offset, curve),
+        Wave::DC(dc) => dc,
+        Wave::BlPulse(width) => bl_pulse(cur_phase, phase_offset, width, phase_inc),
+        Wave::BlSawUp(curve) => bl_saw_up(cur_phase, phase_offset, curve, phase_inc),
+        Wave::BlSawDown(curve) => -bl_saw_up(cur_phase, phase_offset, curve, phase_inc),
+        Wave::Table(index) => tables[index].sample(cur_phase + phase_offset),
+        Wave::WhiteNoise => noise.white(),
+        Wave::PinkNoise => noise.pink(),
+        Wave::RandomDC => noise.level(),
+        Wave::Ramp => 1.0,
+    }
+}
+
+pub fn lin_interp(x: f64, y1: f64, y2: f64) -> f64 {
+    y1 + ((y2 - y1) * x)
+}
+
+pub fn fmod(numer: f64, denom: f64) -> f64 {
+    let rquot = (numer / denom).floor();
+    numer - rquot * denom
+}
+
+pub fn sine(phase: f64, phase_offset: f64) -> f64 {
+    ((phase + phase_offset) * (std::f64::consts::PI * 2.0)).sin()
+}
+
+pub fn cosine(phase: f64, phase_offset: f64) -> f64 {
+    ((phase + phase_offset) * (std::f64::consts::PI * 2.0)).cos()
+}
+
+pub fn saw_up(phase: f64, phase_offset: f64, curve: Curve) -> f64 {
+    let ph = fmod(phase + phase_offset, 1.0);
+    (curve.apply(ph) * 2.0) - 1.0
+}
+
+pub fn saw_down(phase: f64, phase_offset: f64, curve: Curve) -> f64 {
+    -saw_up(phase, phase_offset, curve)
+}
+
+/// Rises from 0 to the peak at `skew / 2`, falls to the trough at
+/// `1 - skew / 2` and rises again, the rising ramp thus taking `skew` of the
+/// cycle.
+pub fn triangle(phase: f64, phase_offset: f64, skew: f64, curve: Curve) -> f64 {
+    let ph = fmod(phase + phase_offset, 1.0);
+    let peak = skew / 2.0;
+    let trough = 1.0 - peak;
+    if ph < peak || ph >= trough {
+        let x = fmod(ph - trough, 1.0) / skew;
+        lin_interp(curve.apply(x), -1.0, 1.0)
+    } else {
+        let x = (ph - peak) / (trough - peak);
+        lin_interp(curve.apply(x), 1.0, -1.0)
+    }
+}
+
+pub fn pulse(phase: f64, phase_offset: f64, width: f64) -> f64 {
+    let ph = fmod(phase + phase_offset, 1.0);
+    if ph < width {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Polynomial approximation of the difference between a band-limited and a
+/// naive unit step at phase `t`, where `dt` is the phase increment.
+fn poly_blep(t: f64, dt: f64) -> f64 {
+    let dt = dt.min(0.5);
+    if t < dt {
+        let t = t / dt;
+        t + t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + t + t + 1.0
+    } else {
+        0.0
+    }
+}
+
+pub fn bl_saw_up(phase: f64, phase_offset: f64, curve: Curve, phase_inc: f64) -> f64 {
+    let ph = fmod(phase + phase_offset, 1.0);
+    saw_up(ph, 0.0, curve) - poly_blep(ph, phase_inc)
+}
+
+pub fn bl_pulse(phase: f64, phase_offset: f64, width: f64, phase_inc: f64) -> f64 {
+    let ph = fmod(phase + phase_offset, 1.0);
+    let naive = if ph < width { 1.0 } else { -1.0 };
+    naive + poly_blep(ph, phase_inc) - poly_blep(fmod(ph + 1.0 - width, 1.0), phase_inc)
+}
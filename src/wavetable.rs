@@ -0,0 +1,108 @@
+use crate::error::{Error, Result};
+use crate::sequence::{parse_floats, read_file};
+use crate::wave::{fmod, lin_interp};
+use std::path::Path;
+
+/// A single cycle of a waveform, referenced as `@name` in waveform
+/// sequences.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wavetable {
+    pub name: String,
+    pub samples: Vec<f64>,
+}
+
+impl Wavetable {
+    /// The cycle at `phase`, linearly interpolated between the samples.
+    pub fn sample(&self, phase: f64) -> f64 {
+        let len = self.samples.len();
+        let position = fmod(phase, 1.0) * len as f64;
+        let i = position.floor() as usize % len;
+        lin_interp(
+            position - position.floor(),
+            self.samples[i],
+            self.samples[(i + 1) % len],
+        )
+    }
+}
+
+/// Index of the table called `name`, later tables shadow earlier ones.
+pub fn find_wavetable(tables: &[Wavetable], name: &str) -> Option<usize> {
+    tables.iter().rposition(|t| t.name == name)
+}
+
+/// Reads the first channel of a WAV file.
+fn read_wav(file_path: &str) -> Result<Vec<f64>> {
+    let wav_error = |source| Error::Wav {
+        path: file_path.to_string(),
+        source,
+    };
+    let mut reader = hound::WavReader::open(file_path).map_err(wav_error)?;
+    let spec = reader.spec();
+    let channels = spec.channels as usize;
+    let samples: Vec<f64> = match spec.sample_format {
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .step_by(channels)
+            .map(|s| s.map(f64::from))
+            .collect::<std::result::Result<_, _>>(),
+        hound::SampleFormat::Int => {
+            let scale = (1u64 << (spec.bits_per_sample - 1)) as f64;
+            reader
+                .samples::<i32>()
+                .step_by(channels)
+                .map(|s| s.map(|s| s as f64 / scale))
+                .collect::<std::result::Result<_, _>>()
+        }
+    }
+    .map_err(wav_error)?;
+    Ok(samples)
+}
+
+/// Loads a table from a WAV file or, for any other extension, from a text
+/// file of whitespace separated values.
+pub fn load_wavetable(name: &str, file_path: &str) -> Result<Wavetable> {
+    let is_wav = Path::new(file_path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("wav"));
+    let samples = if is_wav {
+        read_wav(file_path)?
+    } else {
+        parse_floats(&read_file(file_path)?).map_err(|e| e.in_file(file_path))?
+    };
+    if samples.is_empty() {
+        return Err(Error::EmptyWavetable(file_path.to_string()));
+    }
+    if let Some(index) = samples.iter().position(|s| !s.is_finite()) {
+        return Err(Error::InvalidValue {
+            sequence: "wavetable",
+            index,
+            value: samples[index],
+            expected: "a finite sample",
+        });
+    }
+    Ok(Wavetable {
+        name: name.to_string(),
+        samples,
+    })
+}
+
+/// Loads `name=path` or a plain path, which is named after the file without
+/// its extension.
+pub fn load_named_wavetable(spec: &str) -> Result<Wavetable> {
+    load_named_wavetable_in(spec, Path::new(""))
+}
+
+/// Loads a wavetable like [`load_named_wavetable`], relative paths refer to
+/// `directory`.
+pub(crate) fn load_named_wavetable_in(spec: &str, directory: &Path) -> Result<Wavetable> {
+    let (name, file_path) = match spec.split_once('=') {
+        Some((name, file_path)) => (name.into(), file_path),
+        None => {
+            let name = Path::new(spec)
+                .file_stem()
+                .map_or(spec.into(), |stem| stem.to_string_lossy());
+            (name, spec)
+        }
+    };
+    load_wavetable(&name, &directory.join(file_path).to_string_lossy())
+}